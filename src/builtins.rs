@@ -0,0 +1,358 @@
+use std::fmt;
+use std::rc::Rc;
+use context::Context;
+use env::Env;
+use error::Error;
+use eval;
+use foreign::{is_foreign, send};
+use parser::read_datum;
+use symbol::Symbol;
+use value::Value;
+
+pub type Primitive = fn(&[Value]) -> Result<Value, Error>;
+/// A builtin that runs Lisp code itself, such as `map`, and so needs the
+/// evaluation context.
+pub type Control = fn(&Context, &[Value]) -> Result<Value, Error>;
+type HostFn = dyn Fn(&Context, &[Value]) -> Result<Value, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    Range(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exact(m) => n == m,
+            Arity::AtLeast(m) => n >= m,
+            Arity::Range(lo, hi) => lo <= n && n <= hi,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+            Arity::Range(lo, hi) => write!(f, "{} to {}", lo, hi),
+        }
+    }
+}
+
+/// A procedure implemented in Rust. Its arity is checked by `eval::apply`
+/// before `func` is called, so `func` can index into its arguments.
+pub struct Builtin {
+    pub name: Symbol,
+    pub arity: Arity,
+    pub doc: Option<String>,
+    func: Box<HostFn>,
+}
+
+impl Builtin {
+    /// A builtin accepting any number of arguments and without a docstring.
+    pub fn new<F>(name: &str, func: F) -> Builtin
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+    {
+        Builtin::with_context(name, move |_, args| func(args))
+    }
+
+    pub(crate) fn with_context<F>(name: &str, func: F) -> Builtin
+    where
+        F: Fn(&Context, &[Value]) -> Result<Value, Error> + 'static,
+    {
+        Builtin {
+            name: Symbol::intern(name),
+            arity: Arity::AtLeast(0),
+            doc: None,
+            func: Box::new(func),
+        }
+    }
+
+    pub fn with_arity(mut self, arity: Arity) -> Builtin {
+        self.arity = arity;
+        self
+    }
+
+    pub fn with_doc(mut self, doc: &str) -> Builtin {
+        self.doc = Some(String::from(doc));
+        self
+    }
+
+    pub fn call(&self, ctx: &Context, args: &[Value]) -> Result<Value, Error> {
+        (self.func)(ctx, args)
+    }
+}
+
+const BUILTINS: &[(&str, Arity, Primitive)] = &[
+    ("+", Arity::AtLeast(1), add),
+    ("add", Arity::AtLeast(1), add),
+    ("-", Arity::AtLeast(1), sub),
+    ("sub", Arity::AtLeast(1), sub),
+    ("*", Arity::AtLeast(1), mul),
+    ("mul", Arity::AtLeast(1), mul),
+    ("/", Arity::AtLeast(1), div),
+    ("div", Arity::AtLeast(1), div),
+    ("=", Arity::AtLeast(1), num_eq),
+    ("eq?", Arity::AtLeast(0), eq),
+    ("eqv?", Arity::AtLeast(0), eqv),
+    ("equal?", Arity::AtLeast(0), equal),
+    ("list", Arity::AtLeast(0), list),
+    ("cons", Arity::Exact(2), cons),
+    ("car", Arity::Exact(1), car),
+    ("cdr", Arity::Exact(1), cdr),
+    ("null?", Arity::Exact(1), is_null),
+    ("pair?", Arity::Exact(1), is_pair),
+    ("append", Arity::Exact(2), append),
+    ("symbol?", Arity::Exact(1), is_symbol),
+    ("string->symbol", Arity::Exact(1), string_to_symbol),
+    ("symbol->string", Arity::Exact(1), symbol_to_string),
+    ("gensym", Arity::Range(0, 1), gensym),
+    ("procedure?", Arity::Exact(1), is_procedure),
+    ("documentation", Arity::Exact(1), documentation),
+    ("foreign?", Arity::Exact(1), is_foreign),
+    ("eof-object", Arity::Exact(0), eof_object),
+    ("eof-object?", Arity::Exact(1), is_eof_object),
+    ("read-from-string", Arity::Exact(1), read_from_string),
+];
+
+const CONTROL: &[(&str, Arity, Control)] = &[
+    ("apply", Arity::AtLeast(2), apply),
+    ("map", Arity::AtLeast(2), map),
+    ("send", Arity::AtLeast(2), send),
+    ("eval", Arity::Range(1, 2), eval),
+    ("interaction-environment", Arity::Exact(0), interaction_environment),
+];
+
+/// Binds every builtin in `env`.
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in BUILTINS {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+    for &(name, arity, func) in CONTROL {
+        register(env, Builtin::with_context(name, func).with_arity(arity));
+    }
+}
+
+pub fn register(env: &mut Env, builtin: Builtin) {
+    env.define(builtin.name, Value::Builtin(Rc::new(builtin)));
+}
+
+pub fn type_error(expected: &str, got: &Value) -> Error {
+    Error::Type(format!("expected {}, got {}", expected, got))
+}
+
+pub fn string_arg(v: &Value) -> Result<&str, Error> {
+    match *v {
+        Value::Str(ref s) => Ok(s),
+        ref v => Err(type_error("string", v)),
+    }
+}
+
+fn to_f64(v: &Value) -> Result<f64, Error> {
+    match *v {
+        Value::Int(i) => Ok(i as f64),
+        Value::Float(f) => Ok(f),
+        ref v => Err(type_error("number", v)),
+    }
+}
+
+/// Folds `vals` left to right, staying exact while every operand is an
+/// integer and switching to floats as soon as one is not.
+fn arith<I, F>(vals: &[Value], int_op: I, float_op: F) -> Result<Value, Error>
+where
+    I: Fn(i64, i64) -> Result<i64, Error>,
+    F: Fn(f64, f64) -> f64,
+{
+    let (first, rest) = match vals.split_first() {
+        Some(split) => split,
+        None => return Err(Error::Arity(String::from("expected at least one parameter."))),
+    };
+    to_f64(first)?;
+    rest.iter().try_fold(first.clone(), |acc, x| match (acc, x) {
+        (Value::Int(a), &Value::Int(b)) => int_op(a, b).map(Value::Int),
+        (a, b) => Ok(Value::Float(float_op(to_f64(&a)?, to_f64(b)?))),
+    })
+}
+
+fn add(vals: &[Value]) -> Result<Value, Error> {
+    arith(vals, |a, b| a.checked_add(b).ok_or(Error::Overflow), |a, b| a + b)
+}
+
+fn sub(vals: &[Value]) -> Result<Value, Error> {
+    arith(vals, |a, b| a.checked_sub(b).ok_or(Error::Overflow), |a, b| a - b)
+}
+
+fn mul(vals: &[Value]) -> Result<Value, Error> {
+    arith(vals, |a, b| a.checked_mul(b).ok_or(Error::Overflow), |a, b| a * b)
+}
+
+fn div(vals: &[Value]) -> Result<Value, Error> {
+    arith(vals, |a, b| match b {
+        0 => Err(Error::DivisionByZero),
+        _ => a.checked_div(b).ok_or(Error::Overflow),
+    }, |a, b| a / b)
+}
+
+fn num_eq(vals: &[Value]) -> Result<Value, Error> {
+    for v in vals {
+        to_f64(v)?;
+    }
+    Ok(Value::Bool(vals.windows(2).all(|w| match (&w[0], &w[1]) {
+        (&Value::Int(a), &Value::Int(b)) => a == b,
+        (a, b) => to_f64(a).ok() == to_f64(b).ok(),
+    })))
+}
+
+fn all_pairs<F>(f: F, vals: &[Value]) -> Result<Value, Error>
+where
+    F: Fn(&Value, &Value) -> bool,
+{
+    Ok(Value::Bool(vals.windows(2).all(|w| f(&w[0], &w[1]))))
+}
+
+fn eq(vals: &[Value]) -> Result<Value, Error> {
+    all_pairs(Value::is_eq, vals)
+}
+
+fn eqv(vals: &[Value]) -> Result<Value, Error> {
+    all_pairs(Value::is_eqv, vals)
+}
+
+fn equal(vals: &[Value]) -> Result<Value, Error> {
+    all_pairs(Value::is_equal, vals)
+}
+
+fn list(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::list(vals.to_vec()))
+}
+
+fn cons(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::cons(vals[0].clone(), vals[1].clone()))
+}
+
+fn car(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Pair(ref p) => Ok(p.car.clone()),
+        ref v => Err(type_error("pair", v)),
+    }
+}
+
+fn cdr(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Pair(ref p) => Ok(p.cdr.clone()),
+        ref v => Err(type_error("pair", v)),
+    }
+}
+
+fn is_null(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Nil)))
+}
+
+fn is_pair(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Pair(_))))
+}
+
+/// `(append x xs)` puts `x` in front of the list `xs`; with anything other
+/// than a list on the right it builds the two element list `(x y)`.
+fn append(vals: &[Value]) -> Result<Value, Error> {
+    match *vals {
+        [ref v, ref xs @ Value::Nil] | [ref v, ref xs @ Value::Pair(_)] =>
+            Ok(Value::cons(v.clone(), xs.clone())),
+        _ => Ok(Value::list(vals.to_vec())),
+    }
+}
+
+fn is_symbol(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Symbol(_))))
+}
+
+fn string_to_symbol(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Symbol(Symbol::intern(string_arg(&vals[0])?)))
+}
+
+fn symbol_to_string(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Symbol(s) => Ok(Value::Str(s.name())),
+        ref v => Err(type_error("symbol", v)),
+    }
+}
+
+fn gensym(vals: &[Value]) -> Result<Value, Error> {
+    match *vals {
+        [] => Ok(Value::Symbol(Symbol::gensym("g"))),
+        [Value::Str(ref prefix)] => Ok(Value::Symbol(Symbol::gensym(prefix))),
+        [Value::Symbol(prefix)] => Ok(Value::Symbol(Symbol::gensym(&prefix.name()))),
+        [ref v] => Err(type_error("string or symbol", v)),
+        _ => unreachable!(),
+    }
+}
+
+fn is_procedure(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Closure(_) | Value::Builtin(_) | Value::Continuation(_)
+        | Value::Generator(_) | Value::Yield(_))))
+}
+
+fn eof_object(_: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Eof)
+}
+
+fn is_eof_object(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Eof)))
+}
+
+/// The first datum in a string, or the eof object if there is none.
+fn read_from_string(vals: &[Value]) -> Result<Value, Error> {
+    Ok(read_datum(string_arg(&vals[0])?)?.map_or(Value::Eof, |(datum, _)| Value::from(&datum)))
+}
+
+/// `(eval datum [env])` evaluates `datum` as code, in the current global
+/// environment unless another one is given.
+fn eval(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let env = match vals.get(1) {
+        None => ctx.env(),
+        Some(Value::Environment(env)) => Rc::clone(env),
+        Some(v) => return Err(type_error("environment", v)),
+    };
+    eval::eval(&vals[0].to_datum()?, &env, ctx)
+}
+
+fn interaction_environment(ctx: &Context, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Environment(ctx.env()))
+}
+
+fn to_list(v: &Value) -> Result<Vec<Value>, Error> {
+    v.to_vec().ok_or_else(|| type_error("list", v))
+}
+
+/// `(apply f a b xs)` calls `f` with `a`, `b` and the elements of `xs`.
+fn apply(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let (last, init) = vals[1..].split_last().unwrap();
+    let mut args = init.to_vec();
+    args.extend(to_list(last)?);
+    eval::apply(&vals[0], args, ctx)
+}
+
+/// `(map f xs ys ...)` calls `f` with one element from each list, stopping
+/// at the end of the shortest one.
+fn map(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let lists = vals[1..].iter().map(to_list).collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    let mut result = Vec::with_capacity(len);
+    for i in 0..len {
+        result.push(eval::apply(&vals[0], lists.iter().map(|l| l[i].clone()).collect(), ctx)?);
+    }
+    Ok(Value::list(result))
+}
+
+/// The docstring of a builtin, or `#f` if it has none.
+fn documentation(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Builtin(ref b) => Ok(b.doc.as_ref().map_or(Value::Bool(false), |d| Value::Str(d.as_str().into()))),
+        Value::Closure(_) => Ok(Value::Bool(false)),
+        ref v => Err(type_error("procedure", v)),
+    }
+}
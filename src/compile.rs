@@ -0,0 +1,635 @@
+use std::rc::Rc;
+use std::slice;
+use env::{Env, Global};
+use error::Error;
+use expr::Expr;
+use builtins::{type_error, Builtin, Primitive};
+use module::{ImportSet, ModuleDef};
+use pattern::{Clause, Pattern};
+use record;
+use symbol::{kw, Symbol};
+use value::Value;
+
+/// A datum after analysis: special forms are recognised, variables are
+/// resolved to a frame slot or a global cell, and quoted data is turned
+/// into values once, up front.
+pub enum Node {
+    Const(Value),
+    Local(usize, usize),
+    Global(Rc<Global>),
+    SetLocal(usize, usize, Rc<Node>),
+    SetGlobal(Rc<Global>, Rc<Node>),
+    DefineGlobal(Rc<Global>, Rc<Node>),
+    If(Rc<Node>, Rc<Node>, Rc<Node>),
+    Lambda(Rc<Lambda>),
+    Seq(Rc<[Rc<Node>]>),
+    Module(Rc<ModuleDef>),
+    Import(Vec<ImportSet>),
+    /// Runs the body, and on a catchable error the handler, in a frame of
+    /// its own holding the condition.
+    Guard(Rc<Node>, Rc<Node>),
+    Raise(Rc<Node>),
+    /// A promise of calling the thunk the node evaluates to; `true` for
+    /// `delay-force`.
+    Delay(Rc<Node>, bool),
+    /// Evaluates the value and runs the first clause it matches.
+    Match(Rc<Node>, Rc<[Clause]>),
+    Call(Rc<Node>, Rc<[Rc<Node>]>),
+}
+
+pub struct Lambda {
+    pub name: Option<Symbol>,
+    pub required: usize,
+    pub rest: bool,
+    pub frame_size: usize,
+    pub body: Rc<Node>,
+}
+
+pub fn compile(expr: &Expr, env: &mut Env) -> Result<Node, Error> {
+    Compiler { env, scopes: Vec::new() }.compile(expr)
+}
+
+struct Compiler<'e> {
+    env: &'e mut Env,
+    scopes: Vec<Vec<Symbol>>,
+}
+
+pub fn syntax_error<T>(form: &str, expr: &[Expr]) -> Result<T, Error> {
+    Err(Error::Syntax(format!("{} in {}", form, Expr::List(expr.to_vec()))))
+}
+
+pub fn symbols(exprs: &[Expr]) -> Result<Vec<Symbol>, Error> {
+    exprs.iter()
+        .map(|x| match *x {
+            Expr::Symbol(s) => Ok(s),
+            ref x => Err(Error::Syntax(format!("expected identifier, got {}", x))),
+        })
+        .collect()
+}
+
+/// The parameter names of a lambda list and whether the last one collects
+/// the remaining arguments.
+fn param_names(expr: &Expr) -> Result<(Vec<Symbol>, bool), Error> {
+    match *expr {
+        Expr::List(ref names) => Ok((symbols(names)?, false)),
+        Expr::DottedList(ref names, ref rest) => {
+            let mut names = symbols(names)?;
+            names.extend(symbols(&[(**rest).clone()])?);
+            Ok((names, true))
+        }
+        Expr::Symbol(rest) => Ok((vec![rest], true)),
+        ref x => Err(Error::Syntax(format!("bad parameter list {}", x))),
+    }
+}
+
+/// The names bound by a `define` or `define-record-type` form, used to
+/// reserve slots for internal defines before the body is compiled.
+fn defined_names(form: &Expr) -> Vec<Symbol> {
+    match *form {
+        Expr::List(ref items) if items.first() == Some(&Expr::Symbol(kw::DEFINE_RECORD_TYPE)) =>
+            record_definitions(items).map(|defs| defs.iter().map(|def| def.name).collect()).unwrap_or_default(),
+        _ => defined_name(form).into_iter().collect(),
+    }
+}
+
+fn defined_name(form: &Expr) -> Option<Symbol> {
+    match *form {
+        Expr::List(ref items) if items.first() == Some(&Expr::Symbol(kw::DEFINE)) => {
+            match items.get(1) {
+                Some(&Expr::Symbol(name)) => Some(name),
+                Some(&Expr::List(ref sig)) | Some(&Expr::DottedList(ref sig, _)) => match sig.first() {
+                    Some(&Expr::Symbol(name)) => Some(name),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn quoted(datum: Expr) -> Expr {
+    Expr::List(vec![Expr::Symbol(kw::QUOTE), datum])
+}
+
+/// A definition made by `define-record-type`: the name, and the record
+/// procedure called with the given arguments to produce its value.
+struct RecordDef {
+    name: Symbol,
+    maker: (&'static str, Primitive),
+    args: Vec<Expr>,
+}
+
+/// The definitions `(define-record-type name (constructor field...)
+/// predicate (field accessor [modifier])...)` stands for, made with
+/// `make-record-type` and the procedures that take a record type. The
+/// constructor may be a bare name, taking every field.
+fn record_definitions(items: &[Expr]) -> Result<Vec<RecordDef>, Error> {
+    let (name, ctor, pred, specs) = match *items {
+        [_, Expr::Symbol(name), ref ctor, Expr::Symbol(pred), ref specs @ ..] => (name, ctor, pred, specs),
+        _ => return syntax_error("define-record-type expects a name, a constructor and a predicate", items),
+    };
+    let def = |def: Symbol, maker, args| RecordDef { name: def, maker, args };
+    let mut fields = Vec::new();
+    let mut procs = Vec::new();
+    for spec in specs {
+        match *spec {
+            Expr::List(ref spec) => match *symbols(spec)?.as_slice() {
+                [field, ref procedures @ ..] if procedures.len() <= 2 => {
+                    fields.push(Expr::Symbol(field));
+                    let makers = [record::ACCESSOR, record::MODIFIER];
+                    for (&proc, &maker) in procedures.iter().zip(makers.iter()) {
+                        procs.push(def(proc, maker, vec![Expr::Symbol(name), quoted(Expr::Symbol(field))]));
+                    }
+                }
+                _ => return syntax_error("bad field spec", items),
+            },
+            _ => return syntax_error("bad field spec", items),
+        }
+    }
+    let (ctor, args) = match *ctor {
+        Expr::Symbol(ctor) => (ctor, vec![Expr::Symbol(name)]),
+        Expr::List(ref sig) => match *symbols(sig)?.as_slice() {
+            [ctor, ref params @ ..] =>
+                (ctor, vec![Expr::Symbol(name), quoted(Expr::List(params.iter().map(|&p| Expr::Symbol(p)).collect()))]),
+            _ => return syntax_error("bad constructor", items),
+        },
+        _ => return syntax_error("bad constructor", items),
+    };
+    let mut defs = vec![
+        def(name, record::MAKE_TYPE, vec![quoted(Expr::Symbol(name)), quoted(Expr::List(fields))]),
+        def(ctor, record::CONSTRUCTOR, args),
+        def(pred, record::PREDICATE, vec![Expr::Symbol(name)]),
+    ];
+    defs.extend(procs);
+    Ok(defs)
+}
+
+/// A call of a procedure of the compiler's own, which scripts cannot
+/// rebind.
+fn primitive<F>(name: &str, func: F, args: Vec<Node>) -> Node
+where
+    F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+{
+    let f = Node::Const(Value::Builtin(Rc::new(Builtin::new(name, func))));
+    Node::Call(Rc::new(f), args.into_iter().map(Rc::new).collect::<Vec<_>>().into())
+}
+
+fn cons_node(head: Node, tail: Node) -> Node {
+    match (head, tail) {
+        (Node::Const(head), Node::Const(tail)) => Node::Const(Value::cons(head, tail)),
+        (head, tail) => primitive("cons", |args| Ok(Value::cons(args[0].clone(), args[1].clone())), vec![head, tail]),
+    }
+}
+
+fn splice_node(items: Node, tail: Node) -> Node {
+    primitive("unquote-splicing", |args| match args[0].to_vec() {
+        Some(items) => Ok(Value::list_with_tail(items, args[1].clone())),
+        None => Err(type_error("list to splice", &args[0])),
+    }, vec![items, tail])
+}
+
+impl<'e> Compiler<'e> {
+    fn lookup(&self, name: Symbol) -> Option<(usize, usize)> {
+        self.scopes.iter().rev().enumerate()
+            .filter_map(|(depth, scope)| scope.iter().rposition(|&n| n == name).map(|i| (depth, i)))
+            .next()
+    }
+
+    fn compile(&mut self, expr: &Expr) -> Result<Node, Error> {
+        match *expr {
+            Expr::Symbol(kw::TRUE) => Ok(Node::Const(Value::Bool(true))),
+            Expr::Symbol(kw::FALSE) => Ok(Node::Const(Value::Bool(false))),
+            Expr::Symbol(kw::UNIT) => Ok(Node::Const(Value::Unit)),
+            Expr::Symbol(name) => Ok(match self.lookup(name) {
+                Some((depth, index)) => Node::Local(depth, index),
+                None => Node::Global(self.env.cell(name)),
+            }),
+            Expr::List(ref items) => self.compile_list(items),
+            Expr::DottedList(..) => Err(Error::Syntax(format!("unexpected dotted list {}", expr))),
+            _ => Ok(Node::Const(Value::from(expr))),
+        }
+    }
+
+    fn compile_list(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let (head, args) = match items.split_first() {
+            Some(split) => split,
+            None => return Err(Error::Syntax(String::from("missing procedure in ()"))),
+        };
+        if let Expr::Symbol(name) = *head {
+            if self.lookup(name).is_none() {
+                match name {
+                    kw::QUOTE => return self.compile_quote(items),
+                    kw::IF => return self.compile_if(items),
+                    kw::AND => return self.compile_and(args),
+                    kw::OR => return self.compile_or(args),
+                    kw::DEFINE => return self.compile_define(items),
+                    kw::LAMBDA => match args.split_first() {
+                        Some((params, body)) => return self.compile_lambda(None, params, body),
+                        None => return syntax_error("missing parameters", items),
+                    },
+                    kw::BEGIN => return self.compile_seq(args),
+                    kw::SET => return self.compile_set(items),
+                    kw::GUARD => return self.compile_guard(items),
+                    kw::TRY => return self.compile_try(items),
+                    kw::DELAY => return self.compile_delay(items, false),
+                    kw::DELAY_FORCE => return self.compile_delay(items, true),
+                    kw::STREAM_CONS => return self.compile_stream_cons(items),
+                    kw::QUASIQUOTE => return match *items {
+                        [_, ref datum] => self.compile_quasi(datum, 1),
+                        _ => syntax_error("quasiquote expects one datum", items),
+                    },
+                    kw::MATCH => return self.compile_match(items),
+                    kw::DEFINE_RECORD_TYPE => return self.compile_record_type(items),
+                    kw::MODULE | kw::DEFINE_LIBRARY | kw::IMPORT if !self.scopes.is_empty() =>
+                        return syntax_error("modules and imports are only allowed at the top level", items),
+                    kw::MODULE => return Ok(Node::Module(Rc::new(ModuleDef::parse_module(items)?))),
+                    kw::DEFINE_LIBRARY => return Ok(Node::Module(Rc::new(ModuleDef::parse_library(items)?))),
+                    kw::IMPORT => return Ok(Node::Import(ImportSet::parse_all(args)?)),
+                    _ => {}
+                }
+            }
+        }
+        Ok(Node::Call(Rc::new(self.compile(head)?), self.compile_args(args)?.into()))
+    }
+
+    fn compile_args(&mut self, args: &[Expr]) -> Result<Vec<Rc<Node>>, Error> {
+        args.iter().map(|x| self.compile(x).map(Rc::new)).collect()
+    }
+
+    fn compile_seq(&mut self, body: &[Expr]) -> Result<Node, Error> {
+        match body.len() {
+            0 => Ok(Node::Const(Value::Unit)),
+            1 => self.compile(&body[0]),
+            _ => Ok(Node::Seq(self.compile_args(body)?.into())),
+        }
+    }
+
+    fn compile_quote(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        match *items {
+            [_, ref datum] => Ok(Node::Const(Value::from(datum))),
+            _ => syntax_error("quote expects one datum", items),
+        }
+    }
+
+    fn compile_if(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let (cond, then, otherwise) = match *items {
+            [_, ref cond, ref then] => (cond, then, None),
+            [_, ref cond, ref then, ref otherwise] => (cond, then, Some(otherwise)),
+            _ => return syntax_error("wrongly defined if", items),
+        };
+        Ok(Node::If(
+            Rc::new(self.compile(cond)?),
+            Rc::new(self.compile(then)?),
+            Rc::new(match otherwise {
+                Some(x) => self.compile(x)?,
+                None => Node::Const(Value::Unit),
+            })))
+    }
+
+    /// `(and a b ...)` is `(if a (and b ...) #f)`, and `(and a)` is `a`.
+    fn compile_and(&mut self, args: &[Expr]) -> Result<Node, Error> {
+        match args.split_first() {
+            None => Ok(Node::Const(Value::Bool(true))),
+            Some((last, [])) => self.compile(last),
+            Some((first, rest)) => Ok(Node::If(
+                Rc::new(self.compile(first)?),
+                Rc::new(self.compile_and(rest)?),
+                Rc::new(Node::Const(Value::Bool(false))))),
+        }
+    }
+
+    /// `(or a b ...)` is `(if a a (or b ...))`, with `a` evaluated once: it
+    /// is passed to a lambda whose parameter no program can name.
+    fn compile_or(&mut self, args: &[Expr]) -> Result<Node, Error> {
+        match args.split_first() {
+            None => Ok(Node::Const(Value::Bool(false))),
+            Some((last, [])) => self.compile(last),
+            Some((first, rest)) => {
+                let first = self.compile(first)?;
+                self.scopes.push(vec![Symbol::intern("or value")]);
+                let rest = self.compile_or(rest);
+                self.scopes.pop();
+                let value = Rc::new(Node::Local(0, 0));
+                let body = Node::If(Rc::clone(&value), value, Rc::new(rest?));
+                let lambda = Lambda { name: None, required: 1, rest: false, frame_size: 1, body: Rc::new(body) };
+                Ok(Node::Call(Rc::new(Node::Lambda(Rc::new(lambda))), vec![Rc::new(first)].into()))
+            }
+        }
+    }
+
+    fn compile_define(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let (name, value) = match *items {
+            // (define name value)
+            [_, Expr::Symbol(name), ref value] => (name, self.compile_named(name, value)?),
+            // (define name (args) body)
+            [_, Expr::Symbol(name), ref params, ref body @ ..] if !body.is_empty() =>
+                (name, self.compile_lambda(Some(name), params, body)?),
+            // (define (name . args) body)
+            [_, Expr::List(ref sig), ref body @ ..] if !body.is_empty() => match sig.split_first() {
+                Some((&Expr::Symbol(name), params)) =>
+                    (name, self.compile_lambda(Some(name), &Expr::List(params.to_vec()), body)?),
+                _ => return syntax_error("cannot define var/function", items),
+            },
+            [_, Expr::DottedList(ref sig, ref rest), ref body @ ..] if !body.is_empty() => match sig.split_first() {
+                Some((&Expr::Symbol(name), params)) => {
+                    let params = if params.is_empty() {
+                        (**rest).clone()
+                    } else {
+                        Expr::DottedList(params.to_vec(), rest.clone())
+                    };
+                    (name, self.compile_lambda(Some(name), &params, body)?)
+                }
+                _ => return syntax_error("cannot define var/function", items),
+            },
+            _ => return syntax_error("cannot define var/function", items),
+        };
+        self.define(name, value, items)
+    }
+
+    fn define(&mut self, name: Symbol, value: Node, items: &[Expr]) -> Result<Node, Error> {
+        if self.scopes.is_empty() {
+            return Ok(Node::DefineGlobal(self.env.cell(name), Rc::new(value)));
+        }
+        match self.scopes.last().and_then(|s| s.iter().rposition(|&n| n == name)) {
+            Some(index) => Ok(Node::SetLocal(0, index, Rc::new(value))),
+            None => syntax_error("define is only allowed at the top level or in a body", items),
+        }
+    }
+
+    /// The record procedures are called directly, so rebinding their global
+    /// names does not change what `define-record-type` means.
+    fn compile_record_type(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let mut nodes = Vec::new();
+        for def in record_definitions(items)? {
+            let args = def.args.iter().map(|arg| self.compile(arg)).collect::<Result<Vec<_>, _>>()?;
+            let (maker, func) = def.maker;
+            let value = primitive(maker, func, args);
+            nodes.push(Rc::new(self.define(def.name, value, items)?));
+        }
+        Ok(Node::Seq(nodes.into()))
+    }
+
+    fn compile_set(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        match *items {
+            [_, Expr::Symbol(name), ref value] => {
+                let value = Rc::new(self.compile(value)?);
+                Ok(match self.lookup(name) {
+                    Some((depth, index)) => Node::SetLocal(depth, index, value),
+                    None => Node::SetGlobal(self.env.cell(name), value),
+                })
+            }
+            _ => syntax_error("set! expects a name and a value", items),
+        }
+    }
+
+    /// `(guard (e clause...) body...)`, where each clause is `(test expr...)`
+    /// or a final `(else expr...)`. If no clause applies, the condition is
+    /// raised again.
+    fn compile_guard(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let (var, clauses, body) = match *items {
+            [_, Expr::List(ref spec), ref body @ ..] if !body.is_empty() => match spec.split_first() {
+                Some((&Expr::Symbol(var), clauses)) => (var, clauses, body),
+                _ => return syntax_error("guard expects (variable clause...)", items),
+            },
+            _ => return syntax_error("guard expects (variable clause...) and a body", items),
+        };
+        let body = self.compile_seq(body)?;
+        self.scopes.push(vec![var]);
+        let handler = self.compile_clauses(clauses);
+        self.scopes.pop();
+        Ok(Node::Guard(Rc::new(body), Rc::new(handler?)))
+    }
+
+    fn compile_clauses(&mut self, clauses: &[Expr]) -> Result<Node, Error> {
+        let mut node = Node::Raise(Rc::new(Node::Local(0, 0)));
+        for (n, clause) in clauses.iter().enumerate().rev() {
+            node = match *clause {
+                Expr::List(ref c) if c.len() >= 2 && c[0] == Expr::Symbol(kw::ELSE) && n == clauses.len() - 1 =>
+                    self.compile_seq(&c[1..])?,
+                Expr::List(ref c) if c.len() >= 2 =>
+                    Node::If(Rc::new(self.compile(&c[0])?), Rc::new(self.compile_seq(&c[1..])?), Rc::new(node)),
+                ref c => return Err(Error::Syntax(format!("bad guard clause {}", c))),
+            };
+        }
+        Ok(node)
+    }
+
+    /// `(try body... (catch e handler...))` handles every catchable error.
+    fn compile_try(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let (var, handler, body) = match items.split_last() {
+            Some((Expr::List(catch), body)) if body.len() >= 2 => match *catch.as_slice() {
+                [Expr::Symbol(kw::CATCH), Expr::Symbol(var), ref handler @ ..] if !handler.is_empty() =>
+                    (var, handler, &body[1..]),
+                _ => return syntax_error("try must end with (catch variable handler...)", items),
+            },
+            _ => return syntax_error("try must end with (catch variable handler...)", items),
+        };
+        let body = self.compile_seq(body)?;
+        self.scopes.push(vec![var]);
+        let handler = self.compile_seq(handler);
+        self.scopes.pop();
+        Ok(Node::Guard(Rc::new(body), Rc::new(handler?)))
+    }
+
+    fn compile_delay(&mut self, items: &[Expr], force: bool) -> Result<Node, Error> {
+        match *items {
+            [_, ref expr] => {
+                let thunk = self.compile_lambda(None, &Expr::List(vec![]), slice::from_ref(expr))?;
+                Ok(Node::Delay(Rc::new(thunk), force))
+            }
+            _ => syntax_error("delay expects one expression", items),
+        }
+    }
+
+    /// `(stream-cons a b)` is `(cons a (delay b))`, whatever `cons` is bound
+    /// to.
+    fn compile_stream_cons(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        match *items {
+            [_, ref head, ref tail] => {
+                let head = self.compile(head)?;
+                let tail = self.compile_delay(&[Expr::Symbol(kw::DELAY), tail.clone()], false)?;
+                Ok(cons_node(head, tail))
+            }
+            _ => syntax_error("stream-cons expects a head and a tail", items),
+        }
+    }
+
+    /// A quasiquoted datum `depth` quasiquotes deep: unquoted expressions at
+    /// depth one are evaluated, and everything else is quoted.
+    fn compile_quasi(&mut self, datum: &Expr, depth: usize) -> Result<Node, Error> {
+        match *datum {
+            Expr::List(ref items) => match *items.as_slice() {
+                [Expr::Symbol(kw::UNQUOTE), ref expr] if depth == 1 => self.compile(expr),
+                [Expr::Symbol(kw::UNQUOTE), _] => self.compile_quasi_list(items, None, depth - 1),
+                [Expr::Symbol(kw::QUASIQUOTE), _] => self.compile_quasi_list(items, None, depth + 1),
+                _ => self.compile_quasi_list(items, None, depth),
+            },
+            Expr::DottedList(ref items, ref tail) => self.compile_quasi_list(items, Some(tail), depth),
+            Expr::Vector(ref items) => match self.compile_quasi_list(items, None, depth)? {
+                Node::Const(_) => Ok(Node::Const(Value::from(datum))),
+                list => Ok(primitive("list->vector", |args| Ok(Value::vector(args[0].to_vec().unwrap())), vec![list])),
+            },
+            _ => Ok(Node::Const(Value::from(datum))),
+        }
+    }
+
+    fn compile_quasi_list(&mut self, items: &[Expr], tail: Option<&Expr>, depth: usize) -> Result<Node, Error> {
+        let mut node = match tail {
+            Some(tail) => self.compile_quasi(tail, depth)?,
+            None => Node::Const(Value::Nil),
+        };
+        for item in items.iter().rev() {
+            node = match *item {
+                Expr::List(ref x) if depth == 1 && x.len() == 2 && x[0] == Expr::Symbol(kw::UNQUOTE_SPLICING) =>
+                    splice_node(self.compile(&x[1])?, node),
+                _ => cons_node(self.compile_quasi(item, depth)?, node),
+            };
+        }
+        Ok(node)
+    }
+
+    /// `(match value clause...)`, where each clause is `(pattern body...)`
+    /// or `(pattern (when guard) body...)`. It is an error for no clause to
+    /// match.
+    fn compile_match(&mut self, items: &[Expr]) -> Result<Node, Error> {
+        let (value, clauses) = match *items {
+            [_, ref value, ref clauses @ ..] if !clauses.is_empty() => (value, clauses),
+            _ => return syntax_error("match expects a value and clauses", items),
+        };
+        let value = self.compile(value)?;
+        let clauses = clauses.iter().map(|c| self.compile_match_clause(c)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Node::Match(Rc::new(value), clauses.into()))
+    }
+
+    fn compile_match_clause(&mut self, clause: &Expr) -> Result<Clause, Error> {
+        let (pattern, guard, body) = match *clause {
+            Expr::List(ref c) => match *c.as_slice() {
+                [ref pattern, Expr::List(ref when), ref body @ ..]
+                    if when.len() == 2 && when[0] == Expr::Symbol(kw::WHEN) && !body.is_empty() =>
+                    (pattern, Some(&when[1]), body),
+                [ref pattern, ref body @ ..] if !body.is_empty() => (pattern, None, body),
+                _ => return Err(Error::Syntax(format!("bad match clause {}", clause))),
+            },
+            _ => return Err(Error::Syntax(format!("bad match clause {}", clause))),
+        };
+        let mut names = Vec::new();
+        let pattern = self.compile_pattern(pattern, &mut names)?;
+        for name in body.iter().flat_map(defined_names) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        let frame_size = names.len();
+        self.scopes.push(names);
+        let guard = guard.map(|g| self.compile(g)).transpose();
+        let body = self.compile_seq(body);
+        self.scopes.pop();
+        Ok(Clause { pattern, frame_size, guard: guard?.map(Rc::new), body: Rc::new(body?) })
+    }
+
+    /// A pattern, adding the variables it binds to `names`. Expressions in
+    /// it are compiled in the scope of the `match`.
+    fn compile_pattern(&mut self, pattern: &Expr, names: &mut Vec<Symbol>) -> Result<Pattern, Error> {
+        match *pattern {
+            Expr::Symbol(kw::WILDCARD) => Ok(Pattern::Wildcard),
+            Expr::Symbol(kw::TRUE) => Ok(Pattern::Literal(Value::Bool(true))),
+            Expr::Symbol(kw::FALSE) => Ok(Pattern::Literal(Value::Bool(false))),
+            Expr::Symbol(kw::UNIT) => Ok(Pattern::Literal(Value::Unit)),
+            Expr::Symbol(name) => Ok(match names.iter().position(|&n| n == name) {
+                Some(i) => Pattern::Same(i),
+                None => {
+                    names.push(name);
+                    Pattern::Bind(names.len() - 1)
+                }
+            }),
+            Expr::List(ref items) => match *items.as_slice() {
+                [Expr::Symbol(kw::QUOTE), ref datum] => Ok(Pattern::Literal(Value::from(datum))),
+                [Expr::Symbol(kw::QUASIQUOTE), ref datum] => self.compile_quasi_pattern(datum, names),
+                [Expr::Symbol(kw::PREDICATE), ref pred, ref patterns @ ..] =>
+                    Ok(Pattern::Pred(Rc::new(self.compile(pred)?), self.compile_patterns(patterns, names)?)),
+                [Expr::Symbol(kw::RECORD), ref ty, ref fields @ ..] =>
+                    Ok(Pattern::Record(Rc::new(self.compile(ty)?), self.compile_patterns(fields, names)?)),
+                _ => Ok(Pattern::List(self.compile_patterns(items, names)?, None)),
+            },
+            Expr::DottedList(ref items, ref tail) => Ok(Pattern::List(
+                self.compile_patterns(items, names)?,
+                Some(Box::new(self.compile_pattern(tail, names)?)))),
+            Expr::Vector(ref items) => Ok(Pattern::Vector(self.compile_patterns(items, names)?)),
+            Expr::Hash(_) => Err(Error::Syntax(format!("hash tables cannot be patterns: {}", pattern))),
+            _ => Ok(Pattern::Literal(Value::from(pattern))),
+        }
+    }
+
+    fn compile_patterns(&mut self, patterns: &[Expr], names: &mut Vec<Symbol>) -> Result<Vec<Pattern>, Error> {
+        patterns.iter().map(|p| self.compile_pattern(p, names)).collect()
+    }
+
+    /// A quasiquoted pattern: a datum matched literally, except for the
+    /// patterns unquoted in it.
+    fn compile_quasi_pattern(&mut self, datum: &Expr, names: &mut Vec<Symbol>) -> Result<Pattern, Error> {
+        match *datum {
+            Expr::List(ref items) => match *items.as_slice() {
+                [Expr::Symbol(kw::UNQUOTE), ref pattern] => self.compile_pattern(pattern, names),
+                [Expr::Symbol(kw::UNQUOTE_SPLICING), _] =>
+                    Err(Error::Syntax(format!("unquote-splicing cannot be used in a pattern: {}", datum))),
+                _ => Ok(Pattern::List(self.compile_quasi_patterns(items, names)?, None)),
+            },
+            Expr::DottedList(ref items, ref tail) => Ok(Pattern::List(
+                self.compile_quasi_patterns(items, names)?,
+                Some(Box::new(self.compile_quasi_pattern(tail, names)?)))),
+            Expr::Vector(ref items) => Ok(Pattern::Vector(self.compile_quasi_patterns(items, names)?)),
+            _ => Ok(Pattern::Literal(Value::from(datum))),
+        }
+    }
+
+    fn compile_quasi_patterns(&mut self, items: &[Expr], names: &mut Vec<Symbol>) -> Result<Vec<Pattern>, Error> {
+        items.iter().map(|x| self.compile_quasi_pattern(x, names)).collect()
+    }
+
+    /// Compiles the value of a definition, naming it if it is a lambda.
+    fn compile_named(&mut self, name: Symbol, value: &Expr) -> Result<Node, Error> {
+        if let Expr::List(ref items) = *value {
+            if let [Expr::Symbol(kw::LAMBDA), ref params, ref body @ ..] = *items.as_slice() {
+                if self.lookup(kw::LAMBDA).is_none() && !body.is_empty() {
+                    return self.compile_lambda(Some(name), params, body);
+                }
+            }
+        }
+        self.compile(value)
+    }
+
+    fn compile_lambda(&mut self, name: Option<Symbol>, params: &Expr, body: &[Expr]) -> Result<Node, Error> {
+        if body.is_empty() {
+            return Err(Error::Syntax(String::from("lambda without a body")));
+        }
+        let (mut names, rest) = param_names(params)?;
+        let required = names.len() - rest as usize;
+        for name in body.iter().flat_map(defined_names) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        let frame_size = names.len();
+
+        self.scopes.push(names);
+        let body = self.compile_seq(body);
+        self.scopes.pop();
+
+        Ok(Node::Lambda(Rc::new(Lambda { name, required, rest, frame_size, body: Rc::new(body?) })))
+    }
+}
+
+#[test]
+fn quasiquote_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define x 2) `(1 ,x ,@(list 3 4) (+ 5 ,x) . ,x)"), s("'(1 2 3 4 (+ 5 2) . 2)"));
+    assert_eq!(s("(define xs '(b c)) `#(a ,@xs)"), s("#(a b c)"));
+    assert_eq!(s("`(1 `(2 ,(3 ,(+ 1 3))))"), s("'(1 (quasiquote (2 (unquote (3 4)))))"));
+    assert_eq!(s("(eval `(+ ,@(list 1 2 3)))"), Ok(Value::Int(6)));
+    assert_eq!(s("`(a ,@1)"), Err(Error::Type(String::from("expected list to splice, got 1"))));
+}
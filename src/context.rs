@@ -0,0 +1,141 @@
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use env::Env;
+use error::Error;
+use module::Modules;
+use value::{self, Value};
+
+/// Resource limits for a single evaluation (one `eval_str`, `eval_file` or
+/// `call`). `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Evaluation steps: every node of the compiled program costs one.
+    pub fuel: Option<u64>,
+    /// Nested procedure calls.
+    pub max_depth: Option<usize>,
+    /// Heap cells allocated: pairs, closures and frame slots.
+    pub max_cells: Option<usize>,
+    pub timeout: Option<Duration>,
+}
+
+/// Unlimited: calls only take room on the evaluator's own stack, so deep
+/// recursion can't overflow the native one.
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits::unlimited()
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Limits {
+        Limits { fuel: None, max_depth: None, max_cells: None, timeout: None }
+    }
+}
+
+/// How deep builtins that call back into Lisp, such as `apply` and `map`,
+/// may nest the evaluator on the native stack, whose frames are a lot
+/// bigger in unoptimised builds. Exceeding it is a `DepthExceeded` whatever
+/// the limits say.
+const MAX_NESTING: usize = if cfg!(debug_assertions) { 200 } else { 1000 };
+
+/// How often, in steps, the clock is looked at.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// The state of one evaluation, passed down to everything that runs code.
+pub struct Context {
+    /// The global environment top-level forms are currently evaluated in:
+    /// the interpreter's, or that of a module being defined.
+    env: RefCell<Rc<RefCell<Env>>>,
+    pub modules: Rc<RefCell<Modules>>,
+    /// The files being loaded, innermost last.
+    pub loading: RefCell<Vec<PathBuf>>,
+    /// The handlers installed by `with-exception-handler`, innermost last.
+    pub handlers: RefCell<Vec<Value>>,
+    limits: Limits,
+    steps: Cell<u64>,
+    depth: Cell<usize>,
+    nesting: Cell<usize>,
+    cells_at_start: usize,
+    deadline: Option<Instant>,
+}
+
+impl Context {
+    pub fn new(limits: Limits, env: Rc<RefCell<Env>>, modules: Rc<RefCell<Modules>>) -> Context {
+        Context {
+            env: RefCell::new(env),
+            modules,
+            loading: RefCell::new(Vec::new()),
+            handlers: RefCell::new(Vec::new()),
+            limits,
+            steps: Cell::new(0),
+            depth: Cell::new(0),
+            nesting: Cell::new(0),
+            cells_at_start: value::cells_allocated(),
+            deadline: limits.timeout.map(|t| Instant::now() + t),
+        }
+    }
+
+    pub fn env(&self) -> Rc<RefCell<Env>> {
+        Rc::clone(&self.env.borrow())
+    }
+
+    /// Runs `f` with `env` as the current global environment.
+    pub fn with_env<T, F: FnOnce() -> T>(&self, env: &Rc<RefCell<Env>>, f: F) -> T {
+        let previous = self.env.replace(Rc::clone(env));
+        let result = f();
+        *self.env.borrow_mut() = previous;
+        result
+    }
+
+    /// Accounts for one evaluation step.
+    pub fn tick(&self) -> Result<(), Error> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if self.limits.fuel.is_some_and(|fuel| steps > fuel) {
+            return Err(Error::FuelExhausted);
+        }
+        if let Some(max) = self.limits.max_cells {
+            if value::cells_allocated() - self.cells_at_start > max {
+                return Err(Error::MemoryExceeded);
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if steps.is_multiple_of(CLOCK_INTERVAL) && Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Enters a procedure call; every successful `enter` must be paired
+    /// with a `leave`.
+    pub fn enter(&self) -> Result<(), Error> {
+        let depth = self.depth.get();
+        if self.limits.max_depth.is_some_and(|max| depth >= max) {
+            return Err(Error::DepthExceeded);
+        }
+        self.depth.set(depth + 1);
+        Ok(())
+    }
+
+    pub fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    /// Starts a run of the evaluator on top of the ones already going; every
+    /// successful `nest` must be paired with an `unnest`.
+    pub fn nest(&self) -> Result<(), Error> {
+        let nesting = self.nesting.get();
+        if nesting >= MAX_NESTING {
+            return Err(Error::DepthExceeded);
+        }
+        self.nesting.set(nesting + 1);
+        Ok(())
+    }
+
+    pub fn unnest(&self) {
+        self.nesting.set(self.nesting.get() - 1);
+    }
+}
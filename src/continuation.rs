@@ -0,0 +1,120 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use builtins::{register, Arity, Builtin, Control};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::apply;
+use value::Value;
+
+/// The continuation of a `call/cc`. Continuations are escape-only: invoking
+/// one unwinds the native stack back to its `call/cc`, so it can only be
+/// used while that call is still running.
+pub struct Continuation {
+    id: usize,
+    active: Cell<bool>,
+}
+
+thread_local! {
+    static NEXT_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+impl Continuation {
+    fn new() -> Continuation {
+        let id = NEXT_ID.with(|n| {
+            n.set(n.get() + 1);
+            n.get()
+        });
+        Continuation { id, active: Cell::new(true) }
+    }
+
+    /// Makes the `call/cc` this continuation belongs to return the argument,
+    /// or `unit` if there is none.
+    pub fn invoke(&self, mut args: Vec<Value>) -> Result<Value, Error> {
+        if !self.active.get() {
+            return Err(Error::Type(String::from(
+                "continuation invoked after its call/cc returned; continuations are escape-only")));
+        }
+        let value = match args.len() {
+            0 => Value::Unit,
+            1 => args.pop().unwrap(),
+            n => return Err(Error::Arity(format!("#<continuation> expects 0 to 1, got {}", n))),
+        };
+        Err(Error::Escape(self.id, value))
+    }
+}
+
+const CONTROL: &[(&str, Arity, Control)] = &[
+    ("call-with-current-continuation", Arity::Exact(1), call_cc),
+    ("call/cc", Arity::Exact(1), call_cc),
+    ("call-with-escape-continuation", Arity::Exact(1), call_cc),
+    ("dynamic-wind", Arity::Exact(3), dynamic_wind),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in CONTROL {
+        register(env, Builtin::with_context(name, func).with_arity(arity));
+    }
+}
+
+/// `(call/cc f)` calls `f` with the continuation of the `call/cc` itself.
+fn call_cc(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let k = Rc::new(Continuation::new());
+    let result = apply(&vals[0], vec![Value::Continuation(Rc::clone(&k))], ctx);
+    k.active.set(false);
+    match result {
+        Err(e) => match *e.root() {
+            Error::Escape(id, ref value) if id == k.id => Ok(value.clone()),
+            _ => Err(e),
+        },
+        result => result,
+    }
+}
+
+/// `(dynamic-wind before thunk after)` calls the three in order; `after`
+/// also runs when `thunk` is left by an escape or an error. Only running
+/// out of a resource skips it.
+fn dynamic_wind(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    apply(&vals[0], vec![], ctx)?;
+    let result = apply(&vals[1], vec![], ctx);
+    if let Err(ref e) = result {
+        if e.is_resource_limit() {
+            return result;
+        }
+    }
+    apply(&vals[2], vec![], ctx)?;
+    result
+}
+
+#[test]
+fn continuations_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(+ 1 (call/cc (lambda (k) (+ 10 (k 2)))))"), Ok(Value::Int(3)));
+    assert_eq!(s("(call-with-current-continuation (lambda (k) 5))"), Ok(Value::Int(5)));
+    assert_eq!(s("(define (find-first pred xs)
+                    (call/cc (lambda (return)
+                      (for-each (lambda (x) (if (pred x) (return x))) xs)
+                      #f)))
+                  (list (find-first zero? '(3 0 2)) (find-first zero? '(1 2)))"), s("'(0 #f)"));
+    assert_eq!(s("(call/cc (lambda (outer) (call/cc (lambda (inner) (outer 'out))) 'not-reached))"), s("'out"));
+    assert_eq!(s("(call/cc (lambda (k) (guard (e (#t 'caught)) (k 'escaped))))"), s("'escaped"));
+    assert_eq!(s("(list (procedure? call/cc) (call/cc procedure?))"), s("'(#t #t)"));
+    assert_eq!(s("(define saved #f) (call/cc (lambda (k) (set! saved k))) (saved 1)"),
+               Err(Error::Type(String::from("continuation invoked after its call/cc returned; continuations are escape-only"))));
+    assert_eq!(s("(call/cc (lambda (k) (k 1 2)))"), Err(Error::Arity(String::from("#<continuation> expects 0 to 1, got 2"))));
+
+    assert_eq!(s("(define trace '())
+                  (define (note x) (set! trace (cons x trace)))
+                  (call/cc (lambda (k)
+                    (dynamic-wind (lambda () (note 'before))
+                                  (lambda () (k 'ignored) (note 'not-reached))
+                                  (lambda () (note 'after)))))
+                  (guard (e (#t (note e)))
+                    (dynamic-wind (lambda () (note 'in)) (lambda () (raise 'boom)) (lambda () (note 'out))))
+                  (reverse trace)"), s("'(before after in out boom)"));
+    assert_eq!(s("(dynamic-wind (lambda () 1) (lambda () 2) (lambda () 3))"), Ok(Value::Int(2)));
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use builtins::{type_error, Arity, Builtin};
+use error::Error;
+use symbol::Symbol;
+use value::Value;
+
+/// Conversion of a Rust value into a Lisp one.
+pub trait ToLisp {
+    fn to_lisp(self) -> Value;
+}
+
+/// Conversion of a Lisp value into a Rust one, failing with a type error
+/// when the value has the wrong shape.
+pub trait FromLisp: Sized {
+    fn from_lisp(value: &Value) -> Result<Self, Error>;
+}
+
+impl ToLisp for Value {
+    fn to_lisp(self) -> Value {
+        self
+    }
+}
+
+impl FromLisp for Value {
+    fn from_lisp(value: &Value) -> Result<Value, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl ToLisp for () {
+    fn to_lisp(self) -> Value {
+        Value::Unit
+    }
+}
+
+impl ToLisp for i64 {
+    fn to_lisp(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl FromLisp for i64 {
+    fn from_lisp(value: &Value) -> Result<i64, Error> {
+        match *value {
+            Value::Int(i) => Ok(i),
+            ref v => Err(type_error("integer", v)),
+        }
+    }
+}
+
+impl ToLisp for f64 {
+    fn to_lisp(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+/// Integers are accepted too.
+impl FromLisp for f64 {
+    fn from_lisp(value: &Value) -> Result<f64, Error> {
+        match *value {
+            Value::Float(f) => Ok(f),
+            Value::Int(i) => Ok(i as f64),
+            ref v => Err(type_error("number", v)),
+        }
+    }
+}
+
+impl ToLisp for bool {
+    fn to_lisp(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromLisp for bool {
+    fn from_lisp(value: &Value) -> Result<bool, Error> {
+        match *value {
+            Value::Bool(b) => Ok(b),
+            ref v => Err(type_error("boolean", v)),
+        }
+    }
+}
+
+impl ToLisp for String {
+    fn to_lisp(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl ToLisp for &str {
+    fn to_lisp(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl FromLisp for String {
+    fn from_lisp(value: &Value) -> Result<String, Error> {
+        Rc::<str>::from_lisp(value).map(|s| String::from(&*s))
+    }
+}
+
+/// Shares the string with the script instead of copying it.
+impl FromLisp for Rc<str> {
+    fn from_lisp(value: &Value) -> Result<Rc<str>, Error> {
+        match *value {
+            Value::Str(ref s) => Ok(Rc::clone(s)),
+            ref v => Err(type_error("string", v)),
+        }
+    }
+}
+
+impl ToLisp for Symbol {
+    fn to_lisp(self) -> Value {
+        Value::Symbol(self)
+    }
+}
+
+impl FromLisp for Symbol {
+    fn from_lisp(value: &Value) -> Result<Symbol, Error> {
+        match *value {
+            Value::Symbol(s) => Ok(s),
+            ref v => Err(type_error("symbol", v)),
+        }
+    }
+}
+
+impl<T: ToLisp> ToLisp for Vec<T> {
+    fn to_lisp(self) -> Value {
+        Value::list(self.into_iter().map(ToLisp::to_lisp).collect())
+    }
+}
+
+/// Accepts vectors as well as lists.
+impl<T: FromLisp> FromLisp for Vec<T> {
+    fn from_lisp(value: &Value) -> Result<Vec<T>, Error> {
+        let items = match *value {
+            Value::Vector(ref items) => items.borrow().clone(),
+            ref v => v.to_vec().ok_or_else(|| type_error("list", v))?,
+        };
+        items.iter().map(T::from_lisp).collect()
+    }
+}
+
+/// `None` is `#f`, the usual "nothing found" answer in scripts. As a
+/// consequence `Some(false)` and `None` both read back as `None`.
+impl<T: ToLisp> ToLisp for Option<T> {
+    fn to_lisp(self) -> Value {
+        self.map_or(Value::Bool(false), ToLisp::to_lisp)
+    }
+}
+
+impl<T: FromLisp> FromLisp for Option<T> {
+    fn from_lisp(value: &Value) -> Result<Option<T>, Error> {
+        match *value {
+            Value::Bool(false) => Ok(None),
+            ref v => T::from_lisp(v).map(Some),
+        }
+    }
+}
+
+/// Maps are association lists of `(key . value)` pairs with string keys.
+impl<T: ToLisp> ToLisp for HashMap<String, T> {
+    fn to_lisp(self) -> Value {
+        let mut entries = self.into_iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Value::list(entries.into_iter().map(|(k, v)| Value::cons(k.to_lisp(), v.to_lisp())).collect())
+    }
+}
+
+/// Accepts association lists keyed by strings or symbols.
+impl<K, T> FromLisp for HashMap<K, T>
+where
+    K: From<String> + Eq + Hash,
+    T: FromLisp,
+{
+    fn from_lisp(value: &Value) -> Result<HashMap<K, T>, Error> {
+        let entries = value.to_vec().ok_or_else(|| type_error("association list", value))?;
+        let mut map = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            let (key, val) = match entry {
+                Value::Pair(ref p) => (&p.car, &p.cdr),
+                ref v => return Err(type_error("(key . value) pair", v)),
+            };
+            let key = match *key {
+                Value::Symbol(s) => String::from(&*s.name()),
+                ref k => String::from_lisp(k)?,
+            };
+            map.insert(K::from(key), T::from_lisp(val)?);
+        }
+        Ok(map)
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($n:expr; $($t:ident $i:tt),*) => {
+        /// Tuples are fixed length lists.
+        impl<$($t: ToLisp),*> ToLisp for ($($t,)*) {
+            fn to_lisp(self) -> Value {
+                Value::list(vec![$(self.$i.to_lisp()),*])
+            }
+        }
+
+        impl<$($t: FromLisp),*> FromLisp for ($($t,)*) {
+            fn from_lisp(value: &Value) -> Result<($($t,)*), Error> {
+                match value.to_vec() {
+                    Some(ref items) if items.len() == $n => Ok(($($t::from_lisp(&items[$i])?,)*)),
+                    _ => Err(type_error(concat!("list of length ", $n), value)),
+                }
+            }
+        }
+    };
+}
+
+tuple_conversions!(1; A 0);
+tuple_conversions!(2; A 0, B 1);
+tuple_conversions!(3; A 0, B 1, C 2);
+tuple_conversions!(4; A 0, B 1, C 2, D 3);
+
+/// The result of a typed host function: either a plain value or a
+/// `Result` whose error is passed on to the script.
+pub trait IntoLispResult {
+    fn into_lisp_result(self) -> Result<Value, Error>;
+}
+
+impl<T: ToLisp> IntoLispResult for T {
+    fn into_lisp_result(self) -> Result<Value, Error> {
+        Ok(self.to_lisp())
+    }
+}
+
+impl<T: ToLisp> IntoLispResult for Result<T, Error> {
+    fn into_lisp_result(self) -> Result<Value, Error> {
+        self.map(ToLisp::to_lisp)
+    }
+}
+
+/// A Rust function whose parameters can all be converted from Lisp values;
+/// `Args` is the tuple of its parameter types.
+pub trait TypedFn<Args> {
+    const ARITY: usize;
+    fn call_with(&self, name: Symbol, args: &[Value]) -> Result<Value, Error>;
+}
+
+fn arg<T: FromLisp>(name: Symbol, args: &[Value], n: usize) -> Result<T, Error> {
+    T::from_lisp(&args[n]).map_err(|e| match e {
+        Error::Type(msg) => Error::Type(format!("{}, argument {}: {}", name, n + 1, msg)),
+        e => e,
+    })
+}
+
+macro_rules! typed_fn {
+    ($n:expr; $($t:ident $i:tt),*) => {
+        impl<F, R, $($t),*> TypedFn<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> R,
+            R: IntoLispResult,
+            $($t: FromLisp,)*
+        {
+            const ARITY: usize = $n;
+
+            #[allow(unused_variables)]
+            fn call_with(&self, name: Symbol, args: &[Value]) -> Result<Value, Error> {
+                self($(arg::<$t>(name, args, $i)?),*).into_lisp_result()
+            }
+        }
+    };
+}
+
+typed_fn!(0;);
+typed_fn!(1; A 0);
+typed_fn!(2; A 0, B 1);
+typed_fn!(3; A 0, B 1, C 2);
+typed_fn!(4; A 0, B 1, C 2, D 3);
+typed_fn!(5; A 0, B 1, C 2, D 3, E 4);
+
+impl Builtin {
+    /// A builtin wrapping a Rust function with typed parameters. Its arity
+    /// is the number of parameters, and arguments that fail to convert are
+    /// reported with their position.
+    pub fn typed<F, Args>(name: &str, func: F) -> Builtin
+    where
+        F: TypedFn<Args> + 'static,
+    {
+        let sym = Symbol::intern(name);
+        Builtin::new(name, move |args| func.call_with(sym, args))
+            .with_arity(Arity::Exact(F::ARITY))
+    }
+}
+
+#[test]
+fn conversions_test() {
+    assert_eq!(42i64.to_lisp(), Value::Int(42));
+    assert_eq!(i64::from_lisp(&Value::Int(42)), Ok(42));
+    assert_eq!(i64::from_lisp(&Value::Bool(true)), Err(Error::Type(String::from("expected integer, got #t"))));
+    assert_eq!(f64::from_lisp(&Value::Int(2)), Ok(2.0));
+    assert_eq!("abc".to_lisp(), Value::Str("abc".into()));
+    assert_eq!(String::from_lisp(&Value::Str("abc".into())), Ok(String::from("abc")));
+
+    let xs = vec![1i64, 2, 3];
+    assert_eq!(Vec::<i64>::from_lisp(&xs.clone().to_lisp()), Ok(xs));
+    assert_eq!(Some(1i64).to_lisp(), Value::Int(1));
+    assert_eq!(None::<i64>.to_lisp(), Value::Bool(false));
+    assert_eq!(Option::<i64>::from_lisp(&Value::Bool(false)), Ok(None));
+
+    let pair = (1i64, String::from("one"));
+    assert_eq!(<(i64, String)>::from_lisp(&pair.clone().to_lisp()), Ok(pair));
+    assert!(<(i64, String)>::from_lisp(&Value::list(vec![Value::Int(1)])).is_err());
+
+    let mut map = HashMap::new();
+    map.insert(String::from("a"), 1i64);
+    map.insert(String::from("b"), 2i64);
+    assert_eq!(HashMap::<String, i64>::from_lisp(&map.clone().to_lisp()), Ok(map));
+}
+
+#[test]
+fn typed_fn_test() {
+    use interpreter::Interpreter;
+
+    let mut interp = Interpreter::new();
+    interp.register(Builtin::typed("repeat", |s: String, n: i64| s.repeat(n as usize)));
+    interp.register(Builtin::typed("longer?", |s: String, n: i64| s.len() as i64 > n));
+    interp.register(Builtin::typed("safe-div", |a: i64, b: i64| {
+        if b == 0 { Err(Error::DivisionByZero) } else { Ok(a / b) }
+    }));
+    interp.register(Builtin::typed("sum", |xs: Vec<f64>| xs.iter().sum::<f64>()));
+
+    assert_eq!(interp.eval_str("(repeat \"ab\" 3)"), Ok(Value::Str("ababab".into())));
+    assert_eq!(interp.eval_str("(longer? \"abc\" 2)"), Ok(Value::Bool(true)));
+    assert_eq!(interp.eval_str("(safe-div 7 2)"), Ok(Value::Int(3)));
+    assert_eq!(interp.eval_str("(safe-div 7 0)"), Err(Error::DivisionByZero));
+    assert_eq!(interp.eval_str("(sum '(1 2.5))"), Ok(Value::Float(3.5)));
+    assert_eq!(interp.eval_str("(repeat 3 \"ab\")"),
+               Err(Error::Type(String::from("repeat, argument 1: expected string, got 3"))));
+    assert_eq!(interp.eval_str("(repeat \"ab\")"),
+               Err(Error::Arity(String::from("#<procedure repeat> expects 2, got 1"))));
+}
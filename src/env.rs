@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use symbol::Symbol;
+use value::{count_cells, Value};
+
+/// The slots of one activation of a lambda: its parameters followed by its
+/// internal defines, addressed by the indices the compiler assigned.
+pub struct Frame {
+    slots: RefCell<Vec<Value>>,
+    parent: Option<Rc<Frame>>,
+}
+
+impl Frame {
+    pub fn new(slots: Vec<Value>, parent: Option<Rc<Frame>>) -> Rc<Frame> {
+        count_cells(slots.len());
+        Rc::new(Frame { slots: RefCell::new(slots), parent })
+    }
+
+    fn up(&self, depth: usize) -> &Frame {
+        let mut frame = self;
+        for _ in 0..depth {
+            frame = frame.parent.as_ref().expect("compiler emitted a bad frame depth");
+        }
+        frame
+    }
+
+    pub fn get(&self, depth: usize, index: usize) -> Value {
+        self.up(depth).slots.borrow()[index].clone()
+    }
+
+    pub fn set(&self, depth: usize, index: usize, value: Value) {
+        self.up(depth).slots.borrow_mut()[index] = value;
+    }
+}
+
+/// A top-level binding. Compiled code holds on to the cell itself, so a
+/// global may be referenced before it is defined.
+pub struct Global {
+    pub name: Symbol,
+    value: RefCell<Option<Value>>,
+    /// The module's cell, for a name that was imported; it holds the value.
+    import: RefCell<Option<Rc<Global>>>,
+}
+
+impl Global {
+    pub fn get(&self) -> Option<Value> {
+        match *self.import.borrow() {
+            Some(ref cell) => cell.get(),
+            None => self.value.borrow().clone(),
+        }
+    }
+
+    /// Assigns to the binding, which for an imported name is the module's.
+    pub fn set(&self, value: Value) {
+        match *self.import.borrow() {
+            Some(ref cell) => cell.set(value),
+            None => *self.value.borrow_mut() = Some(value),
+        }
+    }
+
+    /// Binds the name to `value` in this cell's own environment, replacing
+    /// an import of it rather than assigning to the module's binding.
+    pub fn define(&self, value: Value) {
+        *self.import.borrow_mut() = None;
+        *self.value.borrow_mut() = Some(value);
+    }
+}
+
+#[derive(Default)]
+pub struct Env {
+    globals: HashMap<Symbol, Rc<Global>>,
+}
+
+impl Env {
+    pub fn cell(&mut self, name: Symbol) -> Rc<Global> {
+        Rc::clone(self.globals.entry(name).or_insert_with(|| {
+            Rc::new(Global { name, value: RefCell::new(None), import: RefCell::new(None) })
+        }))
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        self.globals.get(&name).and_then(|g| g.get())
+    }
+
+    pub fn define(&mut self, name: Symbol, value: Value) {
+        self.cell(name).define(value)
+    }
+
+    /// Makes `name` stand for a cell of another environment, so both see
+    /// the same value; this is how imported names are bound. Code already
+    /// compiled against `name` sees the import too.
+    pub fn alias(&mut self, name: Symbol, cell: Rc<Global>) {
+        let own = self.cell(name);
+        if !Rc::ptr_eq(&own, &cell) {
+            *own.value.borrow_mut() = None;
+            *own.import.borrow_mut() = Some(cell);
+        }
+    }
+
+    /// A copy of this environment with cells of its own, so that defining
+    /// a name in one does not affect the other.
+    pub fn fork(&self) -> Env {
+        let mut env = Env::default();
+        for (&name, cell) in &self.globals {
+            if let Some(value) = cell.get() {
+                env.define(name, value);
+            }
+        }
+        env
+    }
+}
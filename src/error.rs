@@ -0,0 +1,102 @@
+use std::error;
+use std::fmt;
+use symbol::Symbol;
+use value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(String),
+    Io(String),
+    Syntax(String),
+    Unbound(Symbol),
+    Type(String),
+    Arity(String),
+    Range(String),
+    DivisionByZero,
+    Overflow,
+    FuelExhausted,
+    DepthExceeded,
+    MemoryExceeded,
+    Timeout,
+    /// Signalled by `(error message irritants...)`.
+    User { message: String, irritants: Vec<Value> },
+    /// A value passed to `raise` that is not an error object.
+    Raised(Value),
+    /// A continuation being invoked, unwinding the stack to its `call/cc`.
+    Escape(usize, Value),
+    /// An error raised while loading a file, with the line of the top-level
+    /// form that was being evaluated.
+    Located { file: String, line: usize, error: Box<Error> },
+}
+
+impl Error {
+    /// Whether this error comes from one of the evaluation `Limits`.
+    pub fn is_resource_limit(&self) -> bool {
+        matches!(*self.root(), Error::FuelExhausted | Error::DepthExceeded | Error::MemoryExceeded | Error::Timeout)
+    }
+
+    /// Whether `guard` and friends may handle this error. Running out of a
+    /// resource is not something a script may recover from, and an escape
+    /// is not an error at all.
+    pub fn is_catchable(&self) -> bool {
+        !self.is_resource_limit() && !matches!(*self.root(), Error::Escape(..))
+    }
+
+    /// The message of the error as an error object: what was passed to
+    /// `error`, or the whole description for other errors.
+    pub fn message(&self) -> String {
+        match *self.root() {
+            Error::User { ref message, .. } => message.clone(),
+            ref e => e.to_string(),
+        }
+    }
+
+    /// The error itself, without where it happened.
+    pub fn root(&self) -> &Error {
+        match *self {
+            Error::Located { ref error, .. } => error.root(),
+            ref e => e,
+        }
+    }
+
+    /// Attributes the error to `line` of `file`, unless it already comes
+    /// from a file, which is then one loaded by `file`.
+    pub fn located(self, file: &str, line: usize) -> Error {
+        match self {
+            e @ Error::Located { .. } => e,
+            e => Error::Located { file: String::from(file), line, error: Box::new(e) },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Parse(ref msg) => write!(f, "parse error: {}", msg),
+            Error::Io(ref msg) => write!(f, "i/o error: {}", msg),
+            Error::Syntax(ref msg) => write!(f, "bad syntax: {}", msg),
+            Error::Unbound(name) => write!(f, "unbound variable: {}", name),
+            Error::Type(ref msg) => write!(f, "type error: {}", msg),
+            Error::Arity(ref msg) => write!(f, "wrong number of arguments: {}", msg),
+            Error::Range(ref msg) => write!(f, "out of range: {}", msg),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::Overflow => write!(f, "integer overflow"),
+            Error::FuelExhausted => write!(f, "evaluation step limit exceeded"),
+            Error::DepthExceeded => write!(f, "call depth limit exceeded"),
+            Error::MemoryExceeded => write!(f, "memory limit exceeded"),
+            Error::Timeout => write!(f, "time limit exceeded"),
+            Error::User { ref message, ref irritants } => {
+                write!(f, "error: {}", message)?;
+                for irritant in irritants {
+                    write!(f, " {}", irritant)?;
+                }
+                Ok(())
+            }
+            Error::Raised(ref value) => write!(f, "uncaught exception: {}", value),
+            Error::Escape(..) => write!(f, "continuation invoked outside of its extent"),
+            Error::Located { ref file, line, ref error } => write!(f, "{}:{}: {}", file, line, error),
+        }
+    }
+}
+
+impl error::Error for Error {}
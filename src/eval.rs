@@ -1,231 +1,414 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+use builtins::type_error;
+use compile::{compile, Node};
+use context::Context;
+use env::{Env, Frame, Global};
+use error::Error;
+use exception::{condition, raised};
 use expr::Expr;
-use parser::parse;
-
-type Name = String;
-type Env = HashMap<Name, Expr>;
-type ArgValue = Expr;
-type Memoized = HashMap<(Name, Vec<ArgValue>), Expr>;
-
-//fix signature to use &str
-pub fn eval<'a, 'b>(exprs: &'b [Expr]) -> Result<(Expr, Env), &'a str> {
-
-    fn eval<'a, 'b>(expr: &'b Expr, env: &mut Env, memoized:&mut Memoized) -> Result<Expr, &'a str> {
-        match *expr {
-            Expr::Symbol(_) | Expr::Fun(_,_) | Expr::QuotedList(_) | Expr::Bool(_) | Expr::Unit | Expr::Int(_) | Expr::Str(_) => Ok(expr.clone()),
-            Expr::Ident(ref val) => {
-                match val.as_str() {
-                    "true" | "#t" => Ok(Expr::Bool(true)),
-                    "false" | "#f" => Ok(Expr::Bool(false)),
-                    "unit" => Ok(Expr::Unit),
-                    _ if env.contains_key(val) => Ok(env[val].clone()),
-                    _ => Ok(Expr::Ident(val.clone())),
+use generator::{Generator, State};
+use module;
+use pattern::Clause;
+use promise::Promise;
+use value::{count_cells, Closure, Value};
+#[cfg(test)]
+use interpreter::Interpreter;
+#[cfg(test)]
+use symbol::Symbol;
+
+/// Compiles `expr` against the globals of `env` and runs it there.
+pub fn eval(expr: &Expr, env: &Rc<RefCell<Env>>, ctx: &Context) -> Result<Value, Error> {
+    let node = Rc::new(compile(expr, &mut env.borrow_mut())?);
+    ctx.with_env(env, || run(Step::Eval(node, None), ctx))
+}
+
+/// Runs an already compiled node in `frame`.
+pub fn eval_node(node: &Rc<Node>, frame: &Option<Rc<Frame>>, ctx: &Context) -> Result<Value, Error> {
+    run(Step::Eval(Rc::clone(node), frame.clone()), ctx)
+}
+
+pub fn apply(f: &Value, args: Vec<Value>, ctx: &Context) -> Result<Value, Error> {
+    run(Step::Apply(f.clone(), args), ctx)
+}
+
+/// What is left to do with a value once it has been computed. The
+/// evaluator keeps these on a stack of its own rather than on the native
+/// one, which is what lets a generator set aside the part of the stack that
+/// belongs to its body and pick it up again later.
+pub enum Kont {
+    If(Rc<Node>, Rc<Node>, Option<Rc<Frame>>),
+    /// The nodes of a sequence from the given index on.
+    Seq(Rc<[Rc<Node>]>, usize, Option<Rc<Frame>>),
+    /// The argument nodes of a call, with the procedure and the arguments
+    /// evaluated so far.
+    Call(Rc<[Rc<Node>]>, Vec<Value>, Option<Rc<Frame>>),
+    SetLocal(usize, usize, Option<Rc<Frame>>),
+    SetGlobal(Rc<Global>),
+    DefineGlobal(Rc<Global>),
+    /// The handler of a `guard` whose body is running.
+    Guard(Rc<Node>, Option<Rc<Frame>>),
+    Raise,
+    Delay(bool),
+    /// The clauses of a `match` whose value is being computed.
+    Match(Rc<[Clause]>, Option<Rc<Frame>>),
+    /// The guard of the clause at the given index, with the value being
+    /// matched, the frame of the `match` and the one of the clause.
+    MatchGuard(Rc<[Clause]>, usize, Value, Option<Rc<Frame>>, Option<Rc<Frame>>),
+    /// The end of a procedure call, where the call depth goes down again.
+    Return,
+    /// The bottom of the body of a running generator.
+    Generator(Rc<Generator>),
+}
+
+enum Step {
+    Eval(Rc<Node>, Option<Rc<Frame>>),
+    Apply(Value, Vec<Value>),
+    Return(Value),
+    Fail(Error),
+}
+
+impl From<Result<Value, Error>> for Step {
+    fn from(result: Result<Value, Error>) -> Step {
+        match result {
+            Ok(v) => Step::Return(v),
+            Err(e) => Step::Fail(e),
+        }
+    }
+}
+
+/// Runs the evaluator until the stack it starts with is empty. Builtins that
+/// call back into Lisp, such as `map`, start a nested run.
+fn run(step: Step, ctx: &Context) -> Result<Value, Error> {
+    ctx.nest()?;
+    let result = run_nested(step, ctx);
+    ctx.unnest();
+    result
+}
+
+fn run_nested(mut step: Step, ctx: &Context) -> Result<Value, Error> {
+    let mut stack = Vec::new();
+    loop {
+        step = match step {
+            Step::Eval(node, frame) => match ctx.tick() {
+                Ok(()) => exec(&node, frame, &mut stack, ctx),
+                Err(e) => Step::Fail(e),
+            },
+            Step::Apply(f, args) => apply_step(&f, args, &mut stack, ctx),
+            Step::Return(value) => match stack.pop() {
+                Some(k) => resume(k, value, &mut stack, ctx),
+                None => return Ok(value),
+            },
+            Step::Fail(e) => match stack.pop() {
+                Some(k) => unwind(k, e, ctx),
+                None => return Err(e),
+            },
+        }
+    }
+}
+
+fn local(frame: &Option<Rc<Frame>>) -> &Frame {
+    frame.as_ref().expect("compiler emitted a local outside of a lambda")
+}
+
+fn exec(node: &Rc<Node>, frame: Option<Rc<Frame>>, stack: &mut Vec<Kont>, ctx: &Context) -> Step {
+    match **node {
+        Node::Const(ref v) => Step::Return(v.clone()),
+        Node::Local(depth, index) => Step::Return(local(&frame).get(depth, index)),
+        Node::Global(ref cell) => cell.get().ok_or(Error::Unbound(cell.name)).into(),
+        Node::SetLocal(depth, index, ref value) => {
+            stack.push(Kont::SetLocal(depth, index, frame.clone()));
+            Step::Eval(Rc::clone(value), frame)
+        }
+        Node::SetGlobal(ref cell, ref value) => {
+            stack.push(Kont::SetGlobal(Rc::clone(cell)));
+            Step::Eval(Rc::clone(value), frame)
+        }
+        Node::DefineGlobal(ref cell, ref value) => {
+            stack.push(Kont::DefineGlobal(Rc::clone(cell)));
+            Step::Eval(Rc::clone(value), frame)
+        }
+        Node::If(ref cond, ref then, ref otherwise) => {
+            stack.push(Kont::If(Rc::clone(then), Rc::clone(otherwise), frame.clone()));
+            Step::Eval(Rc::clone(cond), frame)
+        }
+        Node::Lambda(ref lambda) => {
+            count_cells(1);
+            Step::Return(Value::Closure(Rc::new(Closure {
+                lambda: Rc::clone(lambda),
+                env: frame,
+            })))
+        }
+        Node::Seq(ref nodes) => resume(Kont::Seq(Rc::clone(nodes), 0, frame), Value::Unit, stack, ctx),
+        Node::Module(ref module) => module::define(module, ctx).into(),
+        Node::Import(ref sets) => module::import(sets, ctx).into(),
+        Node::Guard(ref body, ref handler) => {
+            stack.push(Kont::Guard(Rc::clone(handler), frame.clone()));
+            Step::Eval(Rc::clone(body), frame)
+        }
+        Node::Raise(ref value) => {
+            stack.push(Kont::Raise);
+            Step::Eval(Rc::clone(value), frame)
+        }
+        Node::Delay(ref thunk, force) => {
+            stack.push(Kont::Delay(force));
+            Step::Eval(Rc::clone(thunk), frame)
+        }
+        Node::Match(ref value, ref clauses) => {
+            stack.push(Kont::Match(Rc::clone(clauses), frame.clone()));
+            Step::Eval(Rc::clone(value), frame)
+        }
+        Node::Call(ref f, ref args) => {
+            stack.push(Kont::Call(Rc::clone(args), Vec::with_capacity(args.len() + 1), frame.clone()));
+            Step::Eval(Rc::clone(f), frame)
+        }
+    }
+}
+
+/// Continues with `k` now that `value` is known.
+fn resume(k: Kont, value: Value, stack: &mut Vec<Kont>, ctx: &Context) -> Step {
+    match k {
+        Kont::If(then, otherwise, frame) => Step::Eval(if value.is_true() { then } else { otherwise }, frame),
+        Kont::Seq(nodes, next, frame) => match nodes.get(next) {
+            Some(node) => {
+                let node = Rc::clone(node);
+                // The last expression is in tail position, so nothing is left to come back to.
+                if next + 1 < nodes.len() {
+                    stack.push(Kont::Seq(nodes, next + 1, frame.clone()));
                 }
+                Step::Eval(node, frame)
             }
-            Expr::List(ref name, ref values) if name == "define" => {
-                match *values.as_slice() {
-                    [] => Err("cannot define <empty> of value <empty>"),
-                    [Expr::Ident(ref name), ref val] => {
-                        let definition = eval(val, env, memoized)?;
-                        env.insert(name.to_owned(), definition);
-                        Ok(Expr::Unit)
-                    }
-                    [Expr::Ident(ref name), Expr::List(ref first_arg, ref tail_args), ref func] => {
-                        let mut args = tail_args.iter()
-                            .map(|x| if let Expr::Ident(ref v) = *x {
-                                Ok(v.to_owned())
-                            } else {
-                                Err("expected ident")
-                            })
-                            .collect::<Result<Vec<_>, _>>()?;
-                        args.insert(0, first_arg.to_owned());
-                        env.insert(name.to_owned(), Expr::Fun(args, Box::new(func.clone())));
-                        Ok(Expr::Unit)
-                    }
-                    _ => Err("cannot define var/function"),
+            None => Step::Return(value),
+        },
+        Kont::Call(args, mut values, frame) => {
+            values.push(value);
+            match args.get(values.len() - 1) {
+                Some(arg) => {
+                    let arg = Rc::clone(arg);
+                    stack.push(Kont::Call(args, values, frame.clone()));
+                    Step::Eval(arg, frame)
                 }
-            },
-            Expr::List(ref name, ref values) if name == "lambda" => {
-                match *values.as_slice() {
-                    [Expr::List(ref first_arg, ref tail_args), ref func] => {
-                        let mut args = tail_args.iter()
-                            .map(|x| if let Expr::Ident(ref v) = *x {
-                                Ok(v.to_owned())
-                            } else {
-                                Err("expected ident")
-                            })
-                            .collect::<Result<Vec<_>, _>>()?;
-                        args.insert(0, first_arg.to_owned());
-                        Ok(Expr::Fun(args, Box::new(func.clone())))
-                    }
-                    _ => Err("cannot define lambda"),
+                None => {
+                    let f = values.remove(0);
+                    Step::Apply(f, values)
                 }
-            },
-            Expr::List(ref name, ref values) if name == "if" => {
-                match *values.as_slice() {
-                    [ref pattern, ref lhs, ref rhs] => {
-                        if eval(pattern, env, memoized)? == Expr::Bool(true) {
-                            eval(lhs, env, memoized)
-                        }
-                        else{
-                            eval(rhs, env, memoized)
-                        }
-                    }
-                    _ => Err("wrongly defined if"),
+            }
+        }
+        Kont::SetLocal(depth, index, frame) => {
+            local(&frame).set(depth, index, value);
+            Step::Return(Value::Unit)
+        }
+        Kont::SetGlobal(cell) => {
+            if cell.get().is_none() {
+                return Step::Fail(Error::Unbound(cell.name));
+            }
+            cell.set(value);
+            Step::Return(Value::Unit)
+        }
+        Kont::DefineGlobal(cell) => {
+            cell.define(value);
+            Step::Return(Value::Unit)
+        }
+        Kont::Guard(..) => Step::Return(value),
+        Kont::Raise => Step::Fail(raised(value)),
+        Kont::Delay(force) => {
+            count_cells(1);
+            Step::Return(if force { Promise::delay_force(value) } else { Promise::delay(value) })
+        }
+        Kont::Match(clauses, frame) => match_clauses(clauses, 0, value, frame, stack, ctx),
+        Kont::MatchGuard(clauses, i, subject, frame, inner) => {
+            if value.is_true() {
+                Step::Eval(Rc::clone(&clauses[i].body), inner)
+            } else {
+                match_clauses(clauses, i + 1, subject, frame, stack, ctx)
+            }
+        }
+        Kont::Return => {
+            ctx.leave();
+            Step::Return(value)
+        }
+        Kont::Generator(g) => {
+            g.finish();
+            Step::Return(Value::Eof)
+        }
+    }
+}
+
+/// Runs the first of `clauses`, from the one at `from` on, that `value`
+/// matches and whose guard holds.
+fn match_clauses(clauses: Rc<[Clause]>, from: usize, value: Value, frame: Option<Rc<Frame>>,
+                 stack: &mut Vec<Kont>, ctx: &Context) -> Step {
+    for (i, clause) in clauses.iter().enumerate().skip(from) {
+        let mut slots = vec![Value::Unit; clause.frame_size];
+        match clause.pattern.matches(&value, &mut slots, &frame, ctx) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => return Step::Fail(e),
+        }
+        let inner = Some(Frame::new(slots, frame.clone()));
+        return match clause.guard {
+            Some(ref guard) => {
+                let guard = Rc::clone(guard);
+                stack.push(Kont::MatchGuard(Rc::clone(&clauses), i, value, frame, inner.clone()));
+                Step::Eval(guard, inner)
+            }
+            None => Step::Eval(Rc::clone(&clause.body), inner),
+        };
+    }
+    Step::Fail(Error::User { message: String::from("no matching clause for"), irritants: vec![value] })
+}
+
+/// Passes `e` through `k`, which only stops it if `k` is a guard.
+fn unwind(k: Kont, e: Error, ctx: &Context) -> Step {
+    match k {
+        Kont::Guard(handler, frame) if e.is_catchable() =>
+            Step::Eval(handler, Some(Frame::new(vec![condition(e)], frame))),
+        Kont::Return => {
+            ctx.leave();
+            Step::Fail(e)
+        }
+        Kont::Generator(g) => {
+            g.finish();
+            Step::Fail(e)
+        }
+        _ => Step::Fail(e),
+    }
+}
+
+fn apply_step(f: &Value, mut args: Vec<Value>, stack: &mut Vec<Kont>, ctx: &Context) -> Step {
+    match *f {
+        Value::Closure(ref closure) => {
+            let lambda = &closure.lambda;
+            if args.len() < lambda.required || (!lambda.rest && args.len() > lambda.required) {
+                return Step::Fail(Error::Arity(format!("{} expects {}{}, got {}",
+                    f, lambda.required, if lambda.rest { " or more" } else { "" }, args.len())));
+            }
+            if lambda.rest {
+                let rest = args.split_off(lambda.required);
+                args.push(Value::list(rest));
+            }
+            args.resize(lambda.frame_size, Value::Unit);
+            // A call in tail position returns straight to its caller's caller, so it
+            // takes over the caller's frame instead of nesting another one.
+            if !matches!(stack.last(), Some(&Kont::Return)) {
+                if let Err(e) = ctx.enter() {
+                    return Step::Fail(e);
                 }
-            },
-            Expr::List(ref name, ref values) => {
-                let vals = values
-                    .iter()
-                    .map(|v| eval(v, env, memoized))
-                    .collect::<Result<Vec<_>, _>>()?;
-
-                fn i64_calc<'a, F>(f: F, vals: &[Expr]) -> Result<Expr, &'a str>
-                where
-                    F: Fn(i64, i64) -> i64,
-                {
-                    let i = vals.iter()
-                        .map(|x| if let Expr::Int(v) = *x {
-                            Ok(v)
-                        } else {
-                            Err("expected int")
-                        })
-                        .collect::<Result<Vec<_>, _>>()?;
-                    if let Some(init) = i.get(0) {
-                        Ok(Expr::Int(i.iter().skip(1).fold(*init, |acc, &x| f(acc, x))))
-                    }
-                    else{
-                        Err("expected at least one parameter.")
-                    }
-                };
-
-                match name.as_str() {
-                    "+" | "add" => i64_calc(|a, b| a + b, &vals),
-                    "-" | "sub" => i64_calc(|a, b| a - b, &vals),
-                    "/" | "div" => i64_calc(|a, b| a / b, &vals),
-                    "*" | "mul" => i64_calc(|a, b| a * b, &vals),
-                    "list" => Ok(Expr::QuotedList(vals)),
-                    "append" => {
-                        match *vals.as_slice(){
-                            [ref v, Expr::QuotedList(ref xs)] =>
-                                {
-                                    let mut xs = xs.clone();
-                                    xs.insert(0, v.clone());
-                                    Ok(Expr::QuotedList(xs))
-                                },
-                            [ref lhs, ref rhs] =>
-                                {
-                                    Ok(Expr::QuotedList(vec!(lhs.clone(), rhs.clone())))
-                                },
-                            _ => Err("unsupported params for append")
-                        }
-                    },
-                    "eq?" => {
-                        if vals.is_empty() {
-                            Ok(Expr::Bool(true))
-                        }
-                        else{
-                            let first = vals[0].clone();
-                            if vals.iter().all(|x| *x == first) {
-                                Ok(Expr::Bool(true))
-                            }
-                            else{
-                                Ok(Expr::Bool(false))
-                            }
-
-                        }
-                    },
-                    "quote" => {
-                        if values.len() == 1{
-                            if let Expr::List(ref id, ref vals) = values[0] {
-                                let mut vals2 = vals.clone();
-                                vals2.insert(0, Expr::Ident(id.clone()));
-                                Ok(Expr::QuotedList(vals2))    
-                            }
-                            else{
-                                Ok(values[0].clone())
-                            }
-                        }
-                        else {
-                            Ok(Expr::QuotedList(values.clone()))}
-                        },
-                    name if env.contains_key(name) =>{
-                        let val = env[name].clone();
-                        match val {
-                            Expr::Fun(ref names, ref expr) if names.len() == vals.len() => {
-                                let key = (name.to_owned(), vals.clone());
-                                if let Some(val2) = memoized.clone().get(&key) {
-                                    Ok(val2.clone())
-                                }
-                                else{
-                                    let mut env = env.clone();
-
-                                    for (n, val) in names.iter().zip(vals) {                     
-                                        env.insert(n.to_owned(), val);
-                                    }
-                                    
-                                    let x = eval(&expr.clone(), &mut env, memoized)?;
-                                    let _ = memoized.entry(key).or_insert(x.clone());
-                                    Ok(x)
-                                }
-                            },
-                            _ => Ok(val)
-                        }
-                    },
-                    _ => {
-                        let mut atoms = Vec::with_capacity(vals.len());
-                        atoms.push(Expr::Ident(name.to_owned()));
-                        for i in vals {
-                            atoms.push(i);
-                        }
-                        Ok(Expr::QuotedList(atoms))
+                stack.push(Kont::Return);
+            }
+            return Step::Eval(Rc::clone(&lambda.body), Some(Frame::new(args, closure.env.clone())));
+        }
+        Value::Generator(ref g) if args.len() <= 1 => {
+            if let Err(e) = ctx.enter() {
+                return Step::Fail(e);
+            }
+            stack.push(Kont::Return);
+            return resume_generator(g, args.pop().unwrap_or(Value::Unit), stack, ctx);
+        }
+        Value::Yield(ref g) if args.len() <= 1 => {
+            return suspend_generator(g, args.pop().unwrap_or(Value::Unit), stack, ctx);
+        }
+        _ => {}
+    }
+    let result = match *f {
+        Value::Builtin(ref builtin) if !builtin.arity.accepts(args.len()) =>
+            Err(Error::Arity(format!("{} expects {}, got {}", f, builtin.arity, args.len()))),
+        Value::Builtin(ref builtin) => builtin.call(ctx, &args),
+        Value::Continuation(ref k) => k.invoke(args),
+        Value::Generator(_) | Value::Yield(_) =>
+            Err(Error::Arity(format!("{} expects 0 to 1, got {}", f, args.len()))),
+        ref f => Err(type_error("procedure", f)),
+    };
+    result.into()
+}
+
+/// Puts the stack a generator's body was suspended with back on top of
+/// `stack` and makes its `yield` return `sent`.
+fn resume_generator(g: &Rc<Generator>, sent: Value, stack: &mut Vec<Kont>, ctx: &Context) -> Step {
+    let state = g.state.replace(State::Running);
+    match state {
+        State::Fresh(body) => {
+            stack.push(Kont::Generator(Rc::clone(g)));
+            Step::Apply(body, vec![Value::Yield(Rc::clone(g))])
+        }
+        State::Suspended(frames) => {
+            stack.push(Kont::Generator(Rc::clone(g)));
+            for k in frames {
+                if let Kont::Return = k {
+                    if let Err(e) = ctx.enter() {
+                        return Step::Fail(e);
                     }
                 }
+                stack.push(k);
             }
+            Step::Return(sent)
+        }
+        State::Running => Step::Fail(Error::Type(String::from("generator resumed while it is running"))),
+        State::Done => {
+            g.finish();
+            Step::Return(Value::Eof)
         }
     }
-    let mut env = HashMap::new();
-    let mut memoized = HashMap::new();
-    exprs.iter().fold(Ok(Expr::Unit), |_, expr| eval(expr, &mut env, &mut memoized)).map(|x| (x, env))
-    
 }
 
+/// Takes the part of `stack` above the generator's bottom off and returns
+/// `value` from the call that resumed it.
+fn suspend_generator(g: &Rc<Generator>, value: Value, stack: &mut Vec<Kont>, ctx: &Context) -> Step {
+    let bottom = stack.iter().rposition(|k| match *k {
+        Kont::Generator(ref h) => Rc::ptr_eq(g, h),
+        _ => false,
+    });
+    let bottom = match bottom {
+        Some(bottom) => bottom,
+        None => return Step::Fail(Error::Type(String::from(
+            "yield called outside of its generator, or from a procedure called by a builtin"))),
+    };
+    let frames = stack.split_off(bottom + 1);
+    stack.pop();
+    for k in &frames {
+        if let Kont::Return = *k {
+            ctx.leave();
+        }
+    }
+    *g.state.borrow_mut() = State::Suspended(frames);
+    Step::Return(value)
+}
 #[test]
 fn eval_test() {
-    fn s(txt: &str) -> Result<Expr, &str> {
-        parse(&txt.chars().collect::<Vec<char>>())
-            .map(|x| eval(&x.res).map(|(x,_)| x))
-            .unwrap()
-    }
-    assert_eq!(s("(+ (* 2 2) 2 3 )"), Ok(Expr::Int(9)));
-    assert_eq!(s("(list (list 5 6) 7)"), Ok(Expr::QuotedList(
-            vec!(Expr::QuotedList(
-                vec!(Expr::Int(5), Expr::Int(6))), Expr::Int(7)))));
-    assert_eq!(s("(quote (+ 1 2))"), Ok(Expr::QuotedList(
-            vec!(Expr::Ident(String::from("+")), Expr::Int(1), Expr::Int(2)))));
-    assert_eq!(s("(define x 1)"), Ok(Expr::Unit));
-    assert_eq!(s("(append 1 (quote (+ 3))))"), Ok(Expr::QuotedList(
-        vec!(Expr::Int(1), Expr::Ident(String::from("+")), Expr::Int(3)))));
-    assert_eq!(s("(if (eq? 1 1) 5 (6 7))"), Ok(Expr::Int(5)));
-    assert_eq!(s("(if (eq? 1 2) 5 \"abc\")"), Ok(Expr::Str(String::from("abc"))));
-    assert_eq!(s("(eq? 1 1 1)"), Ok(Expr::Bool(true)));
-    assert_eq!(s("(eq? 1 2)"), Ok(Expr::Bool(false)));
-    assert_eq!(s("(eq? (1 2) (1 2) (1 2))"), Ok(Expr::Bool(true)));
-    assert_eq!(s("(eq? (1 2) (1 2) (1 1))"), Ok(Expr::Bool(false)));
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    fn sym(name: &str) -> Value {
+        Value::Symbol(Symbol::intern(name))
+    }
+    assert_eq!(s("(+ (* 2 2) 2 3 )"), Ok(Value::Int(9)));
+    assert_eq!(s("(list (list 5 6) 7)"), Ok(Value::list(
+            vec!(Value::list(vec!(Value::Int(5), Value::Int(6))), Value::Int(7)))));
+    assert_eq!(s("(quote (+ 1 2))"), Ok(Value::list(
+            vec!(sym("+"), Value::Int(1), Value::Int(2)))));
+    assert_eq!(s("(define x 1)"), Ok(Value::Unit));
+    assert_eq!(s("(append 1 (quote (+ 3)))"), Ok(Value::list(
+        vec!(Value::Int(1), sym("+"), Value::Int(3)))));
+    assert_eq!(s("(if (eq? 1 1) 5 (6 7))"), Ok(Value::Int(5)));
+    assert_eq!(s("(if (eq? 1 2) 5 \"abc\")"), Ok(Value::Str("abc".into())));
+    assert_eq!(s("(eq? 1 1 1)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(eq? 1 2)"), Ok(Value::Bool(false)));
+    assert_eq!(s("(equal? '(1 2) '(1 2) '(1 2))"), Ok(Value::Bool(true)));
+    assert_eq!(s("(equal? '(1 2) '(1 2) '(1 1))"), Ok(Value::Bool(false)));
     assert_eq!(s("(define x 'abc)
-                  x"), Ok(Expr::Symbol(String::from("abc"))));
-    assert_eq!(s("(define add2 (a) (+ a 2))"), Ok(Expr::Unit));
+                  x"), Ok(sym("abc")));
+    assert_eq!(s("(define add2 (a) (+ a 2))"), Ok(Value::Unit));
     assert_eq!(
         s(
             "(+           
                  (add 1 2 3)    1 2 (/ 1 2 3)    1 2)",
         ),
-        Ok(Expr::Int(12))
+        Ok(Value::Int(12))
     );
 
     assert_eq!(s("(define add2 (a) (+ a 2))
                   (define nine 9)
-                  (add2 nine)"), Ok(Expr::Int(11)));
+                  (add2 nine)"), Ok(Value::Int(11)));
 
     assert_eq!(s("(define sub1 (z) (- z 1))
 (define sub2 (z) (- z 2))
@@ -234,8 +417,148 @@ fn eval_test() {
 (if (or (eq? a 1) (eq? a 2))
     1
     (+ (fib (sub1 a)) (fib (sub2 a)))))
-(fib 8)"), Ok(Expr::Int(21)));
+(fib 8)"), Ok(Value::Int(21)));
+
 
+    assert_eq!(s("(define id (lambda (a) a)) (id 42)"), Ok(Value::Int(42)));
+}
+
+#[test]
+fn closures_test() {
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define (adder n) (lambda (x) (+ x n))) ((adder 3) 4)"), Ok(Value::Int(7)));
+    assert_eq!(s("(define (counter)
+                    (define n 0)
+                    (lambda () (set! n (+ n 1)) n))
+                  (define c (counter))
+                  (c) (c)"), Ok(Value::Int(2)));
+    assert_eq!(s("(define (f a . rest) rest) (f 1 2 3)"),
+               Ok(Value::list(vec![Value::Int(2), Value::Int(3)])));
+    assert_eq!(s("((lambda args args))"), Ok(Value::Nil));
+    assert_eq!(s("(cons 1 2)"), Ok(Value::cons(Value::Int(1), Value::Int(2))));
+    assert_eq!(s("(car (cdr '(1 2 3)))"), Ok(Value::Int(2)));
+    assert_eq!(s("(if 0 'yes 'no)"), s("'yes"));
+    assert_eq!(s("(+ 1 2.5)"), Ok(Value::Float(3.5)));
+    assert_eq!(s("(/ 1 0)"), Err(Error::DivisionByZero));
+    assert_eq!(s("undefined-name"), Err(Error::Unbound(Symbol::intern("undefined-name"))));
+    assert_eq!(s("(define (f x) x) (f)"), Err(Error::Arity(String::from("#<procedure f> expects 1, got 0"))));
+    assert!(s("(1 2)").is_err());
+}
+
+#[test]
+fn builtins_test() {
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define plus +) (plus 1 2)"), Ok(Value::Int(3)));
+    assert_eq!(s("(map + '(1 2 3) '(10 20))"), Ok(Value::list(vec![Value::Int(11), Value::Int(22)])));
+    assert_eq!(s("(map (lambda (x) (* x x)) '(1 2 3))"),
+               Ok(Value::list(vec![Value::Int(1), Value::Int(4), Value::Int(9)])));
+    assert_eq!(s("(apply + 1 2 '(3 4))"), Ok(Value::Int(10)));
+    assert_eq!(s("((lambda (+) (+ 5 3)) -)"), Ok(Value::Int(2)));
+    assert_eq!(s("(define (list . xs) 'shadowed) (list 1 2)"), s("'shadowed"));
+    assert_eq!(s("(procedure? car)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(eq? car car)"), Ok(Value::Bool(true)));
+    assert_eq!(s("car").map(|x| x.to_string()), Ok(String::from("#<procedure car>")));
+    assert_eq!(s("(car 1 2)"), Err(Error::Arity(String::from("#<procedure car> expects 1, got 2"))));
+    assert_eq!(s("(define xs (vector->list (make-vector 1000000 0))) (car xs)"), Ok(Value::Int(0)));
+    assert_eq!(s("(define (nest n xs) (if (= n 0) xs (nest (- n 1) (list xs)))) (define xs (nest 100000 '())) 0"),
+               Ok(Value::Int(0)));
+}
+
+#[test]
+fn equality_test() {
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(eq? (list 1 2) (list 1 2))"), Ok(Value::Bool(false)));
+    assert_eq!(s("(eqv? (list 1 2) (list 1 2))"), Ok(Value::Bool(false)));
+    assert_eq!(s("(equal? (list 1 (list 2)) (list 1 (list 2)))"), Ok(Value::Bool(true)));
+    assert_eq!(s("(define xs (list 1 2)) (eq? xs xs)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(eq? (list) (list))"), Ok(Value::Bool(true)));
+
+    assert_eq!(s("(eq? \"abc\" \"abc\")"), Ok(Value::Bool(false)));
+    assert_eq!(s("(equal? \"abc\" \"abc\")"), Ok(Value::Bool(true)));
+    assert_eq!(s("(define str \"abc\") (eqv? str str)"), Ok(Value::Bool(true)));
+
+    assert_eq!(s("(eq? 'abc 'abc)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(eqv? 42 42)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(= 42 42 42)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(= 42 43)"), Ok(Value::Bool(false)));
+    assert_eq!(s("(= 42 'abc)"), Err(Error::Type(String::from("expected number, got abc"))));
+
+    assert_eq!(s("(eq? 1.5 1.5)"), Ok(Value::Bool(false)));
+    assert_eq!(s("(eqv? 1.5 1.5)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(eqv? 2 2.0)"), Ok(Value::Bool(false)));
+    assert_eq!(s("(= 2 2.0)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(equal? 2 2.0)"), Ok(Value::Bool(false)));
 
-    assert_eq!(s("(define id (lambda (a) a)) (id 42)"), Ok(Expr::Int(42)));
+    assert_eq!(s("(define f (x) x) (define g (x) x) (equal? f g)"), Ok(Value::Bool(false)));
+    assert_eq!(s("(define f (x) x) (equal? f f)"), Ok(Value::Bool(true)));
+
+    assert_eq!(s("(define (zeros) (vector->list (make-vector 100000 0)))
+                  (list (equal? (zeros) (zeros)) (equal? (zeros) (cons 0 (zeros))))"), s("'(#t #f)"));
+    assert_eq!(s("(define (nest n xs) (if (= n 0) xs (nest (- n 1) (list xs))))
+                  (list (equal? (nest 100000 '()) (nest 100000 '())) (equal? (nest 100000 '()) (nest 100000 '(1))))"),
+               s("'(#t #f)"));
+}
+
+#[test]
+fn symbols_test() {
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(eq? 'a (quote a))"), Ok(Value::Bool(true)));
+    assert_eq!(s("(symbol? 'a)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(symbol? \"a\")"), Ok(Value::Bool(false)));
+    assert_eq!(s("(eq? (string->symbol \"abc\") 'abc)"), Ok(Value::Bool(true)));
+    assert_eq!(s("(symbol->string 'abc)"), Ok(Value::Str("abc".into())));
+    assert_eq!(s("'(+ 1 2)"), s("(quote (+ 1 2))"));
+
+    assert_eq!(s("(symbol? (gensym))"), Ok(Value::Bool(true)));
+    assert_eq!(s("(eq? (gensym) (gensym))"), Ok(Value::Bool(false)));
+    assert_eq!(s("(define g (gensym 'tmp)) (eq? g (string->symbol (symbol->string g)))"),
+               Ok(Value::Bool(false)));
+}
+
+#[test]
+fn code_as_data_test() {
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(read-from-string \"(+ 1 2) ignored\")"), s("'(+ 1 2)"));
+    assert_eq!(s("(eof-object? (read-from-string \"  ; nothing\"))"), Ok(Value::Bool(true)));
+    assert!(s("(read-from-string \"(+ 1\")").is_err());
+
+    assert_eq!(s("(eval '(+ 1 2))"), Ok(Value::Int(3)));
+    assert_eq!(s("(eval (read-from-string \"(* 6 7)\") (interaction-environment))"), Ok(Value::Int(42)));
+    assert_eq!(s("(eval '(define x 5)) (+ x 1)"), Ok(Value::Int(6)));
+    assert_eq!(s("(define (f y) (eval 'y)) (f 1)"), Err(Error::Unbound(Symbol::intern("y"))));
+    assert_eq!(s("(eval (list 'quote (list 1 2 3)))"), s("'(1 2 3)"));
+    assert_eq!(s("(eval (list car ''(1)))"), Err(Error::Type(String::from("expected a datum, got #<procedure car>"))));
+    assert!(s("(eval 1 2)").is_err());
+    assert_eq!(s("(eq? (interaction-environment) (interaction-environment))"), Ok(Value::Bool(true)));
+
+    let depth = ::parser::MAX_NESTING;
+    assert_eq!(s(&format!("{}0{}", "(+ 1 ".repeat(depth), ")".repeat(depth))), Ok(Value::Int(depth as i64)));
+    assert_eq!(s("(define (nest n xs) (if (= n 0) xs (nest (- n 1) (list 'car xs)))) (eval (nest 100000 ''(1)))"),
+               Err(Error::Syntax(format!("code nested more than {} deep", depth))));
+}
+
+#[test]
+fn tail_calls_test() {
+    let mut interp = Interpreter::new();
+    interp.set_limits(::context::Limits { max_depth: Some(10), ..::context::Limits::default() });
+    assert_eq!(interp.eval_str("(define steps 0)
+                                (define (loop n) (if (= n 0) steps (begin (set! steps (+ steps 1)) (loop (- n 1)))))
+                                (loop 100000)"), Ok(Value::Int(100000)));
+    assert_eq!(interp.eval_str("(define (even? n) (if (= n 0) #t (odd? (- n 1))))
+                                (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+                                (even? 100000)"), Ok(Value::Bool(true)));
+    assert_eq!(interp.eval_str("(define (count n acc) (match n (0 acc) (_ (count (- n 1) (+ acc 1))))) (count 100000 0)"),
+               Ok(Value::Int(100000)));
+    assert_eq!(interp.eval_str("(define (deep n) (if (= n 0) 0 (+ 1 (deep (- n 1))))) (deep 20)"),
+               Err(Error::DepthExceeded));
 }
@@ -0,0 +1,190 @@
+use std::rc::Rc;
+use builtins::{register, string_arg, type_error, Arity, Builtin, Control, Primitive};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::apply;
+use value::Value;
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    ("error", Arity::AtLeast(1), error),
+    ("raise", Arity::Exact(1), raise),
+    ("error-object?", Arity::Exact(1), is_error_object),
+    ("error-object-message", Arity::Exact(1), error_object_message),
+    ("error-object-irritants", Arity::Exact(1), error_object_irritants),
+    ("read-error?", Arity::Exact(1), is_read_error),
+    ("file-error?", Arity::Exact(1), is_file_error),
+];
+
+const CONTROL: &[(&str, Arity, Control)] = &[
+    ("with-exception-handler", Arity::Exact(2), with_exception_handler),
+    ("raise-continuable", Arity::Exact(1), raise_continuable),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+    for &(name, arity, func) in CONTROL {
+        register(env, Builtin::with_context(name, func).with_arity(arity));
+    }
+}
+
+/// What a handler is given for `e`: the value passed to `raise`, or an
+/// error object for everything else.
+pub fn condition(e: Error) -> Value {
+    match *e.root() {
+        Error::Raised(ref v) => v.clone(),
+        _ => Value::Error(Rc::new(e)),
+    }
+}
+
+/// The error that raising `v` signals. Raising an error object signals the
+/// error it was made from, so re-raising a caught error is transparent.
+pub fn raised(v: Value) -> Error {
+    match v {
+        Value::Error(e) => (*e).clone(),
+        v => Error::Raised(v),
+    }
+}
+
+/// Runs `body`, handing any error a script may recover from to `handler`.
+pub fn catch<F, H>(body: F, handler: H) -> Result<Value, Error>
+where
+    F: FnOnce() -> Result<Value, Error>,
+    H: FnOnce(Value) -> Result<Value, Error>,
+{
+    match body() {
+        Err(e) if e.is_catchable() => handler(condition(e)),
+        result => result,
+    }
+}
+
+/// `(error message irritants...)` signals an error object.
+fn error(vals: &[Value]) -> Result<Value, Error> {
+    Err(Error::User { message: String::from(string_arg(&vals[0])?), irritants: vals[1..].to_vec() })
+}
+
+fn raise(vals: &[Value]) -> Result<Value, Error> {
+    Err(raised(vals[0].clone()))
+}
+
+fn error_arg(v: &Value) -> Result<&Error, Error> {
+    match *v {
+        Value::Error(ref e) => Ok(e),
+        ref v => Err(type_error("error object", v)),
+    }
+}
+
+fn is_error_object(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Error(_))))
+}
+
+fn error_object_message(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Str(error_arg(&vals[0])?.message().into()))
+}
+
+fn error_object_irritants(vals: &[Value]) -> Result<Value, Error> {
+    match *error_arg(&vals[0])?.root() {
+        Error::User { ref irritants, .. } => Ok(Value::list(irritants.clone())),
+        _ => Ok(Value::Nil),
+    }
+}
+
+fn is_read_error(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(match vals[0] {
+        Value::Error(ref e) => matches!(*e.root(), Error::Parse(_)),
+        _ => false,
+    }))
+}
+
+fn is_file_error(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(match vals[0] {
+        Value::Error(ref e) => matches!(*e.root(), Error::Io(_)),
+        _ => false,
+    }))
+}
+
+/// `(with-exception-handler handler thunk)` calls `thunk`, installing
+/// `handler` for `raise-continuable`. If `thunk` fails, `handler` is called
+/// with the condition under the outer handlers; as the raise was not
+/// continuable, `handler` returning is itself an error, raised to them.
+/// Unlike in R7RS, `thunk` has already been left by the time `handler`
+/// runs, so it should escape, with a continuation or a `raise` of its own.
+fn with_exception_handler(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let handler = &vals[0];
+    ctx.handlers.borrow_mut().push(handler.clone());
+    let result = apply(&vals[1], vec![], ctx);
+    ctx.handlers.borrow_mut().pop();
+    catch(|| result, |condition| {
+        apply(handler, vec![condition.clone()], ctx)?;
+        Err(Error::User {
+            message: String::from("exception handler returned from a non-continuable raise of"),
+            irritants: vec![condition],
+        })
+    })
+}
+
+/// `(raise-continuable obj)` calls the innermost handler with `obj`, outside
+/// of its own extent, and returns what the handler returns.
+fn raise_continuable(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let handler = match ctx.handlers.borrow_mut().pop() {
+        Some(handler) => handler,
+        None => return Err(raised(vals[0].clone())),
+    };
+    let result = apply(&handler, vec![vals[0].clone()], ctx);
+    ctx.handlers.borrow_mut().push(handler);
+    result
+}
+
+#[test]
+fn exceptions_test() {
+    use interpreter::Interpreter;
+    use symbol::Symbol;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(error \"boom\" 1 'x)"),
+               Err(Error::User { message: String::from("boom"), irritants: vec![Value::Int(1), Value::Symbol(Symbol::intern("x"))] }));
+    assert_eq!(s("(error \"boom\" 1 'x)").unwrap_err().to_string(), "error: boom 1 x");
+    assert_eq!(s("(raise 'oops)"), Err(Error::Raised(Value::Symbol(Symbol::intern("oops")))));
+
+    assert_eq!(s("(guard (e (#t (list (error-object? e) (error-object-message e) (error-object-irritants e))))
+                    (error \"boom\" 1 2))"), s("'(#t \"boom\" (1 2))"));
+    assert_eq!(s("(guard (e ((symbol? e) (list 'caught e))) (raise 'oops))"), s("'(caught oops)"));
+    assert_eq!(s("(guard (e ((pair? e) 'no) (else 'fallback)) (raise 42))"), s("'fallback"));
+    assert_eq!(s("(guard (e ((pair? e) 'no)) (raise 42))"), Err(Error::Raised(Value::Int(42))));
+    assert_eq!(s("(guard (e (#t 'unused)) (+ 1 2))"), Ok(Value::Int(3)));
+    assert_eq!(s("(guard (e (#f 'no)) (car 1))"), Err(Error::Type(String::from("expected pair, got 1"))));
+    assert_eq!(s("(guard (e (#t (error-object-message e))) (/ 1 0))"), Ok(Value::Str("division by zero".into())));
+    assert_eq!(s("(guard (e ((error-object? e) (raise e))) (car '()))"),
+               Err(Error::Type(String::from("expected pair, got ()"))));
+    assert_eq!(s("(define (safe-div a b) (try (/ a b) (catch e 'undefined))) (list (safe-div 4 2) (safe-div 1 0))"),
+               s("'(2 undefined)"));
+    assert_eq!(s("(define (f x) (guard (e (#t (+ x e))) (raise 10))) (f 1)"), Ok(Value::Int(11)));
+
+    assert_eq!(s("(call/cc (lambda (k) (with-exception-handler (lambda (e) (k (list 'handled e))) (lambda () (raise 'oops)))))"),
+               s("'(handled oops)"));
+    assert_eq!(s("(with-exception-handler (lambda (e) 'ignored) (lambda () (raise 'oops)))"),
+               Err(Error::User { message: String::from("exception handler returned from a non-continuable raise of"),
+                                 irritants: vec![Value::Symbol(Symbol::intern("oops"))] }));
+    assert_eq!(s("(guard (e ((symbol? e) e))
+                    (with-exception-handler (lambda (e) (raise 'outer)) (lambda () (raise 'inner))))"),
+               Ok(Value::Symbol(Symbol::intern("outer"))));
+    assert_eq!(s("(guard (e ((error-object? e) (error-object-irritants e)))
+                    (with-exception-handler (lambda (e) 0) (lambda () (car 1))))")
+                   .map(|v| v.to_string()),
+               Ok(String::from("(#<error \"type error: expected pair, got 1\">)")));
+    assert_eq!(s("(with-exception-handler (lambda (e) (* e 2)) (lambda () (+ 1 (raise-continuable 20))))"),
+               Ok(Value::Int(41)));
+    assert_eq!(s("(raise-continuable 1)"), Err(Error::Raised(Value::Int(1))));
+    assert_eq!(s("(list (error-object? 1) (read-error? (try (read-from-string \"(\") (catch e e))))"), s("'(#f #t)"));
+    assert!(s("(guard (e) )").is_err());
+    assert!(s("(try 1 2)").is_err());
+
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define (loop n) (loop (+ n 1)))").unwrap();
+    interp.set_limits(::context::Limits { fuel: Some(1000), ..::context::Limits::default() });
+    assert_eq!(interp.eval_str("(try (loop 0) (catch e 'caught))"), Err(Error::FuelExhausted));
+}
@@ -1,44 +1,59 @@
 use std::fmt;
+use symbol::Symbol;
 
-type Ident = String;
-type ArgName = Ident;
-
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+/// A datum as produced by the reader. This is only the syntax of a
+/// program; see `value::Value` for what evaluating it produces.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Int(i64),
+    Float(f64),
     Str(String),
     Bool(bool),
-    Symbol(String),
-    Unit,
-    Ident(Ident),
-    List(Ident, Vec<Expr>),
-    QuotedList(Vec<Expr>),
-    Fun(Vec<ArgName>, Box<Expr>),
+    Symbol(Symbol),
+    List(Vec<Expr>),
+    DottedList(Vec<Expr>, Box<Expr>),
+    Vector(Vec<Expr>),
+    /// A hash table literal, `#hash((key . value) ...)`.
+    Hash(Vec<(Expr, Expr)>),
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Expr::Int(i) => write!(f, "{}", i),
-            Expr::Str(ref i) => write!(f, "\"{}\"", i),
-            Expr::Symbol(ref i) => write!(f, "{}", i),
-            Expr::Ident(ref i) => write!(f, "{}", i),
-            Expr::Bool(i) => write!(f, "{}", i),
-            Expr::Unit => write!(f, "unit"),
-            Expr::List(ref ident, ref items) => {
+            Expr::Float(i) => write!(f, "{:?}", i),
+            Expr::Str(ref i) => write!(f, "{:?}", i),
+            Expr::Symbol(i) => write!(f, "{}", i),
+            Expr::Bool(true) => write!(f, "#t"),
+            Expr::Bool(false) => write!(f, "#f"),
+            Expr::Vector(ref items) => write!(f, "#{}", Expr::List(items.clone())),
+            Expr::Hash(ref entries) => {
+                write!(f, "#hash(")?;
+                for (n, (k, v)) in entries.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "({} . {})", k, v)?;
+                }
+                write!(f, ")")
+            }
+            Expr::List(ref items) => {
                 write!(f, "(")?;
-                write!(f, " {} ", ident)?;
-                for i in items.iter() {
-                    write!(f, " {} ", i)?;
+                for (n, i) in items.iter().enumerate() {
+                    if n > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", i)?;
                 }
-                write!(f, ")")},
-            Expr::QuotedList(ref items) => {
+                write!(f, ")")
+            }
+            Expr::DottedList(ref items, ref tail) => {
                 write!(f, "(")?;
                 for i in items.iter() {
-                    write!(f, " {} ", i)?;
+                    write!(f, "{} ", i)?;
                 }
-                write!(f, ")")},
-            Expr::Fun(_, _) => write!(f, "##fun##"),
+                write!(f, ". {})", tail)
+            }
         }
     }
 }
@@ -0,0 +1,160 @@
+use std::any::{self, Any};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::rc::Rc;
+use builtins::{type_error, Builtin};
+use context::Context;
+use convert::{FromLisp, ToLisp};
+use error::Error;
+use eval;
+use symbol::Symbol;
+use value::Value;
+
+/// Describes a kind of host object: the name it prints with and the
+/// methods scripts can invoke on it with `(send obj 'method args...)`.
+pub struct ForeignType {
+    pub name: String,
+    methods: HashMap<Symbol, Value>,
+}
+
+/// A Rust value owned by a script. Scripts can only hold on to it, compare
+/// it by identity and pass it back to host functions.
+pub struct Foreign {
+    pub ty: Rc<ForeignType>,
+    payload: Box<dyn Any>,
+}
+
+impl ForeignType {
+    pub fn new(name: &str) -> ForeignType {
+        ForeignType { name: String::from(name), methods: HashMap::new() }
+    }
+
+    /// Adds a method. It is called with the object as its first argument,
+    /// followed by the arguments given to `send`.
+    pub fn with_method(mut self, builtin: Builtin) -> ForeignType {
+        self.methods.insert(builtin.name, Value::Builtin(Rc::new(builtin)));
+        self
+    }
+
+    pub fn method(&self, name: Symbol) -> Option<&Value> {
+        self.methods.get(&name)
+    }
+
+    pub fn wrap<T: Any>(self: &Rc<Self>, payload: T) -> Value {
+        Value::Foreign(Rc::new(Foreign { ty: Rc::clone(self), payload: Box::new(payload) }))
+    }
+}
+
+fn short_type_name<T: Any>() -> &'static str {
+    any::type_name::<T>().rsplit("::").next().unwrap_or("foreign")
+}
+
+impl Foreign {
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.payload.downcast_ref()
+    }
+}
+
+impl Value {
+    /// Wraps a host object in a value of its own, method-less type named
+    /// after the Rust type.
+    pub fn foreign<T: Any>(payload: T) -> Value {
+        Rc::new(ForeignType::new(short_type_name::<T>())).wrap(payload)
+    }
+
+    /// The host object inside a foreign value, if it is a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match *self {
+            Value::Foreign(ref f) => f.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// A foreign value known to hold a `T`, for use as a parameter of typed
+/// host functions: `Builtin::typed("query", |db: Handle<Db>, sql: String| ...)`.
+pub struct Handle<T> {
+    foreign: Rc<Foreign>,
+    marker: PhantomData<T>,
+}
+
+impl<T: Any> FromLisp for Handle<T> {
+    fn from_lisp(value: &Value) -> Result<Handle<T>, Error> {
+        match *value {
+            Value::Foreign(ref f) if f.payload.is::<T>() =>
+                Ok(Handle { foreign: Rc::clone(f), marker: PhantomData }),
+            ref v => Err(type_error(short_type_name::<T>(), v)),
+        }
+    }
+}
+
+impl<T> ToLisp for Handle<T> {
+    fn to_lisp(self) -> Value {
+        Value::Foreign(self.foreign)
+    }
+}
+
+impl<T: Any> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.foreign.downcast_ref().expect("handle checked its type on creation")
+    }
+}
+
+/// `(send obj 'method args...)` calls a method of a foreign object's type.
+pub fn send(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let method = match vals[1] {
+        Value::Symbol(s) => s,
+        ref v => return Err(type_error("symbol", v)),
+    };
+    let f = match vals[0] {
+        Value::Foreign(ref obj) => obj.ty.method(method).cloned()
+            .ok_or_else(|| Error::Type(format!("{} has no method {}", obj.ty.name, method)))?,
+        ref v => return Err(type_error("foreign object", v)),
+    };
+    let mut args = vec![vals[0].clone()];
+    args.extend_from_slice(&vals[2..]);
+    eval::apply(&f, args, ctx)
+}
+
+pub fn is_foreign(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Foreign(_))))
+}
+
+#[test]
+fn foreign_test() {
+    use std::cell::RefCell;
+    use interpreter::Interpreter;
+
+    struct Counter {
+        hits: RefCell<i64>,
+    }
+
+    let mut interp = Interpreter::new();
+    let ty = Rc::new(ForeignType::new("counter")
+        .with_method(Builtin::typed("hit!", |c: Handle<Counter>, n: i64| {
+            *c.hits.borrow_mut() += n;
+            *c.hits.borrow()
+        }))
+        .with_method(Builtin::typed("hits", |c: Handle<Counter>| *c.hits.borrow())));
+    interp.define("c", ty.wrap(Counter { hits: RefCell::new(0) }));
+    interp.register(Builtin::typed("reset!", |c: Handle<Counter>| *c.hits.borrow_mut() = 0));
+
+    assert_eq!(interp.eval_str("(send c 'hit! 2) (send c 'hit! 3)"), Ok(Value::Int(5)));
+    assert_eq!(interp.get("c").unwrap().downcast_ref::<Counter>().map(|c| *c.hits.borrow()), Some(5));
+    assert_eq!(interp.eval_str("(reset! c) (send c 'hits)"), Ok(Value::Int(0)));
+    assert_eq!(interp.eval_str("c").map(|c| c.to_string()), Ok(String::from("#<counter>")));
+    assert_eq!(interp.eval_str("(list (foreign? c) (foreign? 1) (eq? c c))"),
+               Ok(Value::list(vec![Value::Bool(true), Value::Bool(false), Value::Bool(true)])));
+    assert_eq!(interp.eval_str("(send c 'frobnicate)"),
+               Err(Error::Type(String::from("counter has no method frobnicate"))));
+    assert_eq!(interp.eval_str("(reset! 1)"),
+               Err(Error::Type(String::from("reset!, argument 1: expected Counter, got 1"))));
+
+    interp.define("s", Value::foreign(String::from("opaque")));
+    assert_eq!(interp.eval_str("s").map(|c| c.to_string()), Ok(String::from("#<String>")));
+    assert_eq!(interp.get("s").unwrap().downcast_ref::<String>().map(String::as_str), Some("opaque"));
+    assert!(interp.get("s").unwrap().downcast_ref::<i64>().is_none());
+}
@@ -0,0 +1,97 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use builtins::{register, Arity, Builtin, Primitive};
+use env::Env;
+use error::Error;
+use eval::Kont;
+use value::Value;
+
+/// A procedure whose body can suspend itself. `(make-generator f)` calls
+/// `f` with a `yield` procedure the first time the generator is called;
+/// `(yield v)` suspends the body and makes that call return `v`, and the
+/// next call resumes it with the argument it was given, if any, as the
+/// value of `yield`. Once the body returns, the generator returns the eof
+/// object, like any SRFI 158 generator.
+pub struct Generator {
+    pub state: RefCell<State>,
+}
+
+pub enum State {
+    /// Not started: the procedure the `yield` procedure is passed to.
+    Fresh(Value),
+    /// Suspended in a `yield`, with what was left of the evaluation stack
+    /// of the body, innermost last.
+    Suspended(Vec<Kont>),
+    Running,
+    Done,
+}
+
+impl Generator {
+    pub fn finish(&self) {
+        *self.state.borrow_mut() = State::Done;
+    }
+}
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    ("make-generator", Arity::Exact(1), make_generator),
+    ("generator?", Arity::Exact(1), is_generator),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+}
+
+fn make_generator(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Generator(Rc::new(Generator { state: RefCell::new(State::Fresh(vals[0].clone())) })))
+}
+
+fn is_generator(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Generator(_))))
+}
+
+#[test]
+fn generators_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define g (make-generator (lambda (yield) (yield 1) (yield 2) 'ignored)))
+                  (list (g) (g) (eof-object? (g)) (eof-object? (g)))"), s("'(1 2 #t #t)"));
+    assert_eq!(s("(define (walk tree yield)
+                    (if (pair? tree) (begin (walk (car tree) yield) (walk (cdr tree) yield))
+                        (if (null? tree) unit (yield tree))))
+                  (define leaves (make-generator (lambda (yield) (walk '((a b) (c (d))) yield))))
+                  (list (leaves) (leaves) (leaves) (leaves))"), s("'(a b c d)"));
+    assert_eq!(s("(define (naturals yield) (define (from n) (yield n) (from (+ n 1))) (from 0))
+                  (define g (make-generator naturals))
+                  (g) (g) (g)"), Ok(Value::Int(2)));
+    assert_eq!(s("(define acc (make-generator (lambda (yield)
+                                (define (loop total) (loop (+ total (yield total))))
+                                (loop 0))))
+                  (list (acc) (acc 5) (acc 10))"), s("'(0 5 15)"));
+    assert_eq!(s("(define g (make-generator (lambda (yield)
+                                (yield (guard (e (#t (list 'caught e))) (yield 'first) (raise 'oops))))))
+                  (list (g) (g))"), s("'(first (caught oops))"));
+    assert_eq!(s("(define g (make-generator (lambda (yield) (yield 1) (car '()))))
+                  (g) (list (guard (e (#t 'failed)) (g)) (eof-object? (g)))"), s("'(failed #t)"));
+    assert_eq!(s("(define outer (make-generator (lambda (yield)
+                                  (define inner (make-generator (lambda (y) (y 'a) (y 'b))))
+                                  (yield (inner)) (yield 'mid) (yield (inner)))))
+                  (list (outer) (outer) (outer))"), s("'(a mid b)"));
+    assert_eq!(s("(list (generator? (make-generator car)) (procedure? (make-generator car)) (generator? car))"),
+               s("'(#t #t #f)"));
+    assert_eq!(s("(define g (make-generator (lambda (yield) (map yield '(1 2))))) (g)"),
+               Err(Error::Type(String::from("yield called outside of its generator, or from a procedure called by a builtin"))));
+    assert_eq!(s("(define g (make-generator (lambda (yield) (g)))) (g)"),
+               Err(Error::Type(String::from("generator resumed while it is running"))));
+    assert_eq!(s("(define y #f) (define g (make-generator (lambda (yield) (set! y yield) 1))) (g) (y 2)"),
+               Err(Error::Type(String::from("yield called outside of its generator, or from a procedure called by a builtin"))));
+
+    let mut interp = Interpreter::new();
+    interp.set_limits(::context::Limits { max_depth: Some(10), ..::context::Limits::default() });
+    let rounds = "(define g (make-generator (lambda (yield) ((lambda () (yield 1) (yield 2)))))) (g) (g) (g) ".repeat(20);
+    assert_eq!(interp.eval_str(&rounds), Ok(Value::Eof));
+}
@@ -0,0 +1,337 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
+use builtins::{register, type_error, Arity, Builtin, Control, Primitive};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::apply;
+use value::{count_cells, Value};
+
+/// A mutable table keyed by `equal?`. Entries are kept in the order they
+/// were added, except that removing one moves the last entry into its
+/// place, so that printing and iteration are deterministic.
+///
+/// Like in other Lisps, a key should not be mutated while it is in a
+/// table.
+#[derive(Clone, Default)]
+pub struct Table {
+    index: HashMap<Key, usize>,
+    entries: Vec<(Value, Value)>,
+}
+
+/// A value hashed and compared the way `equal?` compares it.
+#[derive(Clone)]
+struct Key(Value);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        self.0.is_equal(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_equal(&self.0, state)
+    }
+}
+
+/// How many values, nested ones included, a hash looks at. Values that
+/// are `equal?` are walked in the same order, so they still hash alike,
+/// while long lists hash quickly and data that contain themselves hash at
+/// all.
+const HASH_BUDGET: usize = 64;
+
+/// Hashes what `equal?` looks at. Values only ever `equal?` when they are
+/// the same object, such as procedures, hash by their kind alone.
+pub fn hash_equal<H: Hasher>(v: &Value, state: &mut H) {
+    hash_within(v, HASH_BUDGET, state)
+}
+
+fn hash_within<H: Hasher>(v: &Value, mut budget: usize, state: &mut H) {
+    // Persistent maps and sets hash each entry on its own and add them up,
+    // since equal ones may list their entries in different orders.
+    let nested = budget / 2;
+    let hash = move |v: &Value| {
+        let mut state = DefaultHasher::new();
+        hash_within(v, nested, &mut state);
+        state.finish()
+    };
+    let mut pending = vec![v.clone()];
+    while let Some(v) = pending.pop() {
+        if budget == 0 {
+            return;
+        }
+        budget -= 1;
+        mem::discriminant(&v).hash(state);
+        match v {
+            Value::Bool(b) => b.hash(state),
+            Value::Int(i) => i.hash(state),
+            Value::Float(f) => f.to_bits().hash(state),
+            Value::Str(ref s) => s.hash(state),
+            Value::Symbol(s) => s.hash(state),
+            Value::Pair(ref p) => {
+                pending.push(p.cdr.clone());
+                pending.push(p.car.clone());
+            }
+            Value::Vector(ref items) => pending.extend(items.borrow().iter().take(budget).rev().cloned()),
+            Value::Record(ref r) => pending.extend(r.values().into_iter().take(budget).rev()),
+            Value::PVector(ref v) => pending.extend(v.items().into_iter().take(budget).rev()),
+            Value::Map(ref m) => m.hash_entries(hash).hash(state),
+            Value::Set(ref s) => s.hash_items(hash).hash(state),
+            _ => {}
+        }
+    }
+}
+
+impl Table {
+    pub fn from_entries(entries: Vec<(Value, Value)>) -> Table {
+        let mut table = Table::default();
+        for (key, value) in entries {
+            table.insert(key, value);
+        }
+        table
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.index.get(&Key(key.clone())).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) {
+        match self.index.get(&Key(key.clone())) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                count_cells(1);
+                self.index.insert(Key(key.clone()), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Option<Value> {
+        let i = self.index.remove(&Key(key.clone()))?;
+        let (_, value) = self.entries.swap_remove(i);
+        if let Some((moved, _)) = self.entries.get(i) {
+            self.index.insert(Key(moved.clone()), i);
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl Value {
+    pub fn hash_table(table: Table) -> Value {
+        Value::Hash(Rc::new(RefCell::new(table)))
+    }
+}
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    ("make-hash-table", Arity::Exact(0), make_hash_table),
+    ("hash", Arity::AtLeast(0), hash),
+    ("hash-table?", Arity::Exact(1), is_hash_table),
+    ("hash-ref", Arity::Range(2, 3), hash_ref),
+    ("hash-set!", Arity::Exact(3), hash_set),
+    ("hash-remove!", Arity::Exact(2), hash_remove),
+    ("hash-contains?", Arity::Exact(2), hash_contains),
+    ("hash-count", Arity::Exact(1), hash_count),
+    ("hash-keys", Arity::Exact(1), hash_keys),
+    ("hash-values", Arity::Exact(1), hash_values),
+    ("hash->alist", Arity::Exact(1), hash_to_alist),
+    ("alist->hash-table", Arity::Exact(1), alist_to_hash_table),
+    ("hash-copy", Arity::Exact(1), hash_copy),
+    ("hash-clear!", Arity::Exact(1), hash_clear),
+];
+
+const CONTROL: &[(&str, Arity, Control)] = &[
+    ("hash-update!", Arity::Range(3, 4), hash_update),
+    ("hash-for-each", Arity::Exact(2), hash_for_each),
+    ("hash-map", Arity::Exact(2), hash_map),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+    for &(name, arity, func) in CONTROL {
+        register(env, Builtin::with_context(name, func).with_arity(arity));
+    }
+}
+
+fn table_arg(v: &Value) -> Result<&RefCell<Table>, Error> {
+    match *v {
+        Value::Hash(ref table) => Ok(table),
+        ref v => Err(type_error("hash table", v)),
+    }
+}
+
+fn missing(key: &Value) -> Error {
+    Error::Range(format!("no key {} in hash table", key))
+}
+
+fn make_hash_table(_: &[Value]) -> Result<Value, Error> {
+    Ok(Value::hash_table(Table::default()))
+}
+
+/// `(hash k1 v1 k2 v2 ...)`.
+fn hash(vals: &[Value]) -> Result<Value, Error> {
+    if !vals.len().is_multiple_of(2) {
+        return Err(Error::Arity(String::from("hash expects keys and values in pairs")));
+    }
+    Ok(Value::hash_table(Table::from_entries(vals.chunks(2).map(|kv| (kv[0].clone(), kv[1].clone())).collect())))
+}
+
+fn is_hash_table(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Hash(_))))
+}
+
+/// `(hash-ref table key [default])`; without a default, a missing key is an
+/// error.
+fn hash_ref(vals: &[Value]) -> Result<Value, Error> {
+    match (table_arg(&vals[0])?.borrow().get(&vals[1]), vals.get(2)) {
+        (Some(v), _) | (None, Some(v)) => Ok(v.clone()),
+        (None, None) => Err(missing(&vals[1])),
+    }
+}
+
+fn hash_set(vals: &[Value]) -> Result<Value, Error> {
+    table_arg(&vals[0])?.borrow_mut().insert(vals[1].clone(), vals[2].clone());
+    Ok(Value::Unit)
+}
+
+fn hash_remove(vals: &[Value]) -> Result<Value, Error> {
+    table_arg(&vals[0])?.borrow_mut().remove(&vals[1]);
+    Ok(Value::Unit)
+}
+
+fn hash_contains(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(table_arg(&vals[0])?.borrow().get(&vals[1]).is_some()))
+}
+
+fn hash_count(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Int(table_arg(&vals[0])?.borrow().len() as i64))
+}
+
+fn hash_keys(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::list(table_arg(&vals[0])?.borrow().entries().iter().map(|e| e.0.clone()).collect()))
+}
+
+fn hash_values(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::list(table_arg(&vals[0])?.borrow().entries().iter().map(|e| e.1.clone()).collect()))
+}
+
+fn hash_to_alist(vals: &[Value]) -> Result<Value, Error> {
+    let table = table_arg(&vals[0])?.borrow();
+    Ok(Value::list(table.entries().iter().map(|(k, v)| Value::cons(k.clone(), v.clone())).collect()))
+}
+
+fn alist_to_hash_table(vals: &[Value]) -> Result<Value, Error> {
+    let entries = vals[0].to_vec().ok_or_else(|| type_error("association list", &vals[0]))?;
+    let entries = entries.iter()
+        .map(|entry| match *entry {
+            Value::Pair(ref p) => Ok((p.car.clone(), p.cdr.clone())),
+            ref v => Err(type_error("(key . value) pair", v)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::hash_table(Table::from_entries(entries)))
+}
+
+fn hash_copy(vals: &[Value]) -> Result<Value, Error> {
+    let table = table_arg(&vals[0])?.borrow().clone();
+    count_cells(table.len());
+    Ok(Value::hash_table(table))
+}
+
+fn hash_clear(vals: &[Value]) -> Result<Value, Error> {
+    *table_arg(&vals[0])?.borrow_mut() = Table::default();
+    Ok(Value::Unit)
+}
+
+/// `(hash-update! table key f [default])` sets `key` to `f` applied to its
+/// value, or to `default` if it has none.
+fn hash_update(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let table = table_arg(&vals[0])?;
+    let current = table.borrow().get(&vals[1]).cloned();
+    let current = match (current, vals.get(3)) {
+        (Some(v), _) => v,
+        (None, Some(default)) => default.clone(),
+        (None, None) => return Err(missing(&vals[1])),
+    };
+    let updated = apply(&vals[2], vec![current], ctx)?;
+    table.borrow_mut().insert(vals[1].clone(), updated);
+    Ok(Value::Unit)
+}
+
+/// Calls `f` with the key and value of every entry. `f` sees the entries
+/// the table had when the iteration started.
+fn hash_for_each(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let entries = table_arg(&vals[0])?.borrow().entries().to_vec();
+    for (key, value) in entries {
+        apply(&vals[1], vec![key, value], ctx)?;
+    }
+    Ok(Value::Unit)
+}
+
+/// The list of what `f` returns for the key and value of every entry.
+fn hash_map(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let entries = table_arg(&vals[0])?.borrow().entries().to_vec();
+    let result = entries.into_iter()
+        .map(|(key, value)| apply(&vals[1], vec![key, value], ctx))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::list(result))
+}
+
+#[test]
+fn hash_table_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define h (make-hash-table)) (hash-set! h '(1 \"a\") 'found) (hash-ref h (list 1 \"a\"))"),
+               s("'found"));
+    assert_eq!(s("(define h (hash 'a 1 2.0 2 #(x) 3)) (list (hash-ref h 'a) (hash-ref h 2.0) (hash-ref h #(x)) (hash-ref h 2 'none))"),
+               s("'(1 2 3 none)"));
+    assert_eq!(s("(hash-ref (hash) 'nope)"), Err(Error::Range(String::from("no key nope in hash table"))));
+    assert_eq!(s("(define h (hash 'a 1 'b 2 'c 3)) (hash-remove! h 'a) (hash-set! h 'd 4)
+                  (list (hash-keys h) (hash-values h) (hash-count h) (hash-contains? h 'a) (hash-contains? h 'b))"),
+               s("'((c b d) (3 2 4) 3 #f #t)"));
+    assert_eq!(s("(define counts (make-hash-table))
+                  (for-each (lambda (w) (hash-update! counts w add1 0)) '(a b a c a))
+                  (hash->alist counts)"), s("'((a . 3) (b . 1) (c . 1))"));
+    assert_eq!(s("(hash-update! (hash) 'x add1)"), Err(Error::Range(String::from("no key x in hash table"))));
+    assert_eq!(s("(define total 0) (hash-for-each (hash 'a 1 'b 2) (lambda (k v) (set! total (+ total v)))) total"),
+               Ok(Value::Int(3)));
+    assert_eq!(s("(hash-map (hash 'a 1 'b 2) (lambda (k v) (cons v k)))"), s("'((1 . a) (2 . b))"));
+    assert_eq!(s("(define v (vector 1)) (vector-set! v 0 v) (define w (vector 1)) (vector-set! w 0 w)
+                  (define h (make-hash-table)) (hash-set! h v 'cycle) (hash-ref h w)"), s("'cycle"));
+    assert_eq!(s("(define v (vector 0)) (define m (persistent-map 'k v)) (vector-set! v 0 m)
+                  (define h (make-hash-table)) (hash-set! h m 'map) (hash-ref h m)"), s("'map"));
+    assert_eq!(s("(define (zeros last) (append (vector->list (make-vector 1000 0)) (list last)))
+                  (define h (make-hash-table)) (hash-set! h (zeros 1) 'one) (hash-set! h (zeros 2) 'two)
+                  (list (hash-ref h (zeros 1)) (hash-ref h (zeros 2)) (hash-count h))"), s("'(one two 2)"));
+
+    assert_eq!(s("(hash 'a 1 \"b\" '(2))").map(|v| v.to_string()), Ok(String::from("#hash((a . 1) (\"b\" . (2)))")));
+    assert_eq!(s("#hash((a . 1) (b . 2))"), s("(hash 'b 2 'a 1)"));
+    assert_eq!(s("(hash-ref #hash((a . 1)) 'a)"), Ok(Value::Int(1)));
+    assert_eq!(s("(read-from-string (with-output-to-string (lambda () (write (hash \"k\" #(1))))))"),
+               s("(hash \"k\" #(1))"));
+    assert_eq!(s("(list (equal? (hash 1 2) (hash 1 2)) (eqv? (hash) (hash)) (equal? (hash 1 2) (hash 1 3)))"),
+               s("'(#t #f #f)"));
+    assert_eq!(s("(define h (hash 1 2)) (define c (hash-copy h)) (hash-clear! h) (list (hash-count h) (hash-count c))"),
+               s("'(0 1)"));
+    assert_eq!(s("(alist->hash-table '((a . 1) (a . 2)))"), s("(hash 'a 2)"));
+    assert_eq!(s("(list (hash-table? (hash)) (hash-table? '()))"), s("'(#t #f)"));
+    assert!(s("(hash 'a)").is_err());
+}
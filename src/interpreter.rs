@@ -0,0 +1,383 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use builtins::{install, Builtin};
+use context::{Context, Limits};
+use continuation;
+use env::Env;
+use error::Error;
+use eval::{apply, eval};
+use exception;
+use generator;
+use hash_table;
+use module::Modules;
+use parser::{read, read_lines};
+use persistent;
+use port::{self, Console, Port};
+use promise;
+use record::{self, RecordType};
+use sandbox::{Group, Profile};
+use symbol::Symbol;
+use system;
+use value::Value;
+use vector;
+#[cfg(test)]
+use std::fs;
+
+/// Helpers written in mlisp itself, such as `or`, `sub1` and `filter`.
+const PRELUDE: &str = include_str!("prelude.lisp");
+
+/// An mlisp interpreter together with its global environment. Definitions
+/// made by one call to `eval_str` are visible to the next.
+pub struct Interpreter {
+    env: Rc<RefCell<Env>>,
+    limits: Limits,
+    console: Rc<Console>,
+    modules: Rc<RefCell<Modules>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// An interpreter with every builtin, including files and commands.
+    pub fn new() -> Interpreter {
+        Interpreter::with_profile(Profile::Full)
+    }
+
+    /// An interpreter whose global environment only has the builtins that
+    /// `profile` allows, and the prelude. Without the console group, the
+    /// current input port is empty and the current output port discards
+    /// what is written.
+    pub fn with_profile(profile: Profile) -> Interpreter {
+        let mut interp = Interpreter::bare(profile);
+        interp.load_prelude();
+        interp
+    }
+
+    /// Like `with_profile`, but without the prelude: only builtins are
+    /// defined.
+    pub fn bare(profile: Profile) -> Interpreter {
+        let mut env = Env::default();
+        let console = if profile.allows(Group::Console) {
+            Console::new(Port::stdin(), Port::stdout())
+        } else {
+            Console::new(Port::from_string(""), Port::output("null", io::sink()))
+        };
+        install(&mut env);
+        exception::install(&mut env);
+        continuation::install(&mut env);
+        promise::install(&mut env);
+        generator::install(&mut env);
+        vector::install(&mut env);
+        hash_table::install(&mut env);
+        persistent::install(&mut env);
+        record::install(&mut env);
+        port::install(&mut env, &profile, &console);
+        system::install(&mut env, &profile, &console);
+        let fs = if profile.allows(Group::FsRead) { Some(profile.fs()) } else { None };
+        let modules = Rc::new(RefCell::new(Modules::new(env.fork(), fs)));
+        Interpreter { env: Rc::new(RefCell::new(env)), limits: Limits::default(), console, modules }
+    }
+
+    fn load_prelude(&mut self) {
+        let ctx = self.context();
+        for (line, expr) in read_lines(PRELUDE).expect("the prelude is readable") {
+            if let Err(e) = eval(&expr, &self.env, &ctx) {
+                panic!("prelude.lisp:{}: {}", line, e);
+            }
+        }
+        self.modules.borrow_mut().base = self.env.borrow().fork();
+    }
+
+    /// Sends what `display`, `write` and friends print to `port` instead of
+    /// the standard output.
+    pub fn set_output(&mut self, port: Rc<Port>) {
+        *self.console.output.borrow_mut() = port;
+    }
+
+    /// Makes `read-line` and `read` read from `port` instead of the
+    /// standard input.
+    pub fn set_input(&mut self, port: Rc<Port>) {
+        *self.console.input.borrow_mut() = port;
+    }
+
+    /// Limits applied to each subsequent `eval_str`, `eval_file` or `call`;
+    /// every one of them starts with a fresh budget.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Evaluates every expression in `src` in turn and returns the value of
+    /// the last one, or `unit` if there are none.
+    pub fn eval_str(&mut self, src: &str) -> Result<Value, Error> {
+        let ctx = self.context();
+        let mut result = Value::Unit;
+        for expr in read(src)? {
+            result = eval(&expr, &self.env, &ctx)?;
+        }
+        Ok(result)
+    }
+
+    /// Evaluates a file like `eval_str`, except that errors say which file
+    /// and line they come from and `load`s in it are relative to it.
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Value, Error> {
+        system::load(path.as_ref(), &self.context())
+    }
+
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.env.borrow_mut().define(Symbol::intern(name), value)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.env.borrow().get(Symbol::intern(name))
+    }
+
+    /// Binds a Rust closure as a global procedure. Scripts see it exactly
+    /// like a builtin: `(procedure? name)` holds and it can be passed around
+    /// or shadowed.
+    pub fn register_fn<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, Error> + 'static,
+    {
+        self.register(Builtin::new(name, func))
+    }
+
+    /// Like `register_fn`, for a builtin with a declared arity or docstring.
+    /// Modules see registered builtins too.
+    pub fn register(&mut self, builtin: Builtin) {
+        let name = builtin.name;
+        let value = Value::Builtin(Rc::new(builtin));
+        self.env.borrow_mut().define(name, value.clone());
+        self.modules.borrow_mut().base.define(name, value);
+    }
+
+    /// Binds a record type made by the host under its name, along with the
+    /// procedures `define-record-type` would give it: for a type `point`
+    /// with a field `x`, `make-point`, `point?`, `point-x` and `set-point-x!`.
+    pub fn define_record_type(&mut self, ty: &Rc<RecordType>) {
+        self.define(&ty.name, Value::RecordType(Rc::clone(ty)));
+        for builtin in ty.procedures() {
+            self.register(builtin);
+        }
+    }
+
+    /// Adds a directory to search for the files of imported modules: an
+    /// import of `(utils math)` looks for `utils/math.lisp`. The directory
+    /// of the file being loaded is searched first, then the current
+    /// directory and the directories added here, in order.
+    pub fn add_module_path<P: AsRef<Path>>(&mut self, dir: P) {
+        self.modules.borrow_mut().path.push(dir.as_ref().to_path_buf());
+    }
+
+    /// Calls the procedure bound to `name` in the global environment.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        let f = self.get(name).ok_or_else(|| Error::Unbound(Symbol::intern(name)))?;
+        apply(&f, args.to_vec(), &self.context())
+    }
+
+    fn context(&self) -> Context {
+        Context::new(self.limits, Rc::clone(&self.env), Rc::clone(&self.modules))
+    }
+}
+
+#[test]
+fn interpreter_test() {
+    let mut interp = Interpreter::new();
+    assert_eq!(interp.eval_str("(define (square x) (* x x))"), Ok(Value::Unit));
+    assert_eq!(interp.eval_str("(square 4)"), Ok(Value::Int(16)));
+    assert_eq!(interp.call("square", &[Value::Int(5)]), Ok(Value::Int(25)));
+    assert_eq!(interp.call("cube", &[]), Err(Error::Unbound(Symbol::intern("cube"))));
+
+    interp.define("answer", Value::Int(42));
+    assert_eq!(interp.eval_str("(square answer)"), Ok(Value::Int(1764)));
+    assert_eq!(interp.get("answer"), Some(Value::Int(42)));
+    assert_eq!(interp.get("no-such-thing"), None);
+    assert_eq!(interp.eval_str(""), Ok(Value::Unit));
+
+    let path = ::std::env::temp_dir().join("mlisp_interpreter_test.lisp");
+    fs::write(&path, "(define from-file 7)\n(square from-file)").unwrap();
+    assert_eq!(interp.eval_file(&path), Ok(Value::Int(49)));
+    assert_eq!(interp.get("from-file"), Some(Value::Int(7)));
+    fs::remove_file(&path).unwrap();
+    assert!(interp.eval_file(&path).is_err());
+}
+
+#[test]
+fn register_fn_test() {
+    use std::cell::Cell;
+    use builtins::Arity;
+
+    let mut interp = Interpreter::new();
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    interp.register_fn("count!", move |args| {
+        counter.set(counter.get() + args.len());
+        Ok(Value::Int(counter.get() as i64))
+    });
+    assert_eq!(interp.eval_str("(count! 1 2) (map count! '(a b))"),
+               Ok(Value::list(vec![Value::Int(3), Value::Int(4)])));
+    assert_eq!(calls.get(), 4);
+
+    interp.register(Builtin::new("twice", |args| match args[0] {
+            Value::Int(i) => Ok(Value::Int(i * 2)),
+            _ => Err(Error::Type(String::from("twice expects an integer"))),
+        })
+        .with_arity(Arity::Exact(1))
+        .with_doc("Doubles an integer."));
+    assert_eq!(interp.eval_str("(twice 21)"), Ok(Value::Int(42)));
+    assert_eq!(interp.eval_str("(procedure? twice)"), Ok(Value::Bool(true)));
+    assert_eq!(interp.eval_str("(documentation twice)"), Ok(Value::Str("Doubles an integer.".into())));
+    assert_eq!(interp.eval_str("(twice 1 2)"),
+               Err(Error::Arity(String::from("#<procedure twice> expects 1, got 2"))));
+    assert_eq!(interp.call("twice", &[Value::Int(4)]), Ok(Value::Int(8)));
+}
+
+#[test]
+fn limits_test() {
+    use std::time::Duration;
+
+    let mut interp = Interpreter::new();
+    interp.eval_str("(define (fib n) (if (= n 0) 0 (if (= n 1) 1 (+ (fib (- n 1)) (fib (- n 2))))))
+                     (define (deep n) (if (= n 0) 0 (+ 1 (deep (- n 1)))))
+                     (define (build n acc) (if (= n 0) acc (build (- n 1) (cons n acc))))").unwrap();
+
+    interp.set_limits(Limits { fuel: Some(10_000), ..Limits::default() });
+    assert_eq!(interp.eval_str("(fib 30)"), Err(Error::FuelExhausted));
+    assert_eq!(interp.eval_str("(deep 10)"), Ok(Value::Int(10)));
+    assert_eq!(interp.call("fib", &[Value::Int(30)]), Err(Error::FuelExhausted));
+
+    interp.set_limits(Limits { max_depth: Some(50), ..Limits::default() });
+    assert_eq!(interp.eval_str("(deep 40)"), Ok(Value::Int(40)));
+    assert_eq!(interp.eval_str("(deep 60)"), Err(Error::DepthExceeded));
+
+    interp.set_limits(Limits::default());
+    assert_eq!(interp.eval_str("(deep 100000)"), Ok(Value::Int(100000)));
+    assert_eq!(interp.eval_str("(define (nested n) (if (= n 0) 0 (+ 1 (apply nested (list (- n 1)))))) (nested 100000)"),
+               Err(Error::DepthExceeded));
+
+    interp.set_limits(Limits { max_cells: Some(200), ..Limits::default() });
+    assert_eq!(interp.eval_str("(car (build 10 '()))"), Ok(Value::Int(1)));
+    assert_eq!(interp.eval_str("(build 150 '())"), Err(Error::MemoryExceeded));
+
+    interp.set_limits(Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() });
+    assert_eq!(interp.eval_str("(fib 40)"), Err(Error::Timeout));
+
+    assert!(Error::Timeout.is_resource_limit());
+    assert!(!Error::DivisionByZero.is_resource_limit());
+}
+
+#[test]
+fn profile_test() {
+    fn bound(profile: Profile, name: &str) -> bool {
+        Interpreter::with_profile(profile).get(name).is_some()
+    }
+    let root = ::std::env::temp_dir();
+    assert!(bound(Profile::Pure, "car"));
+    assert!(!bound(Profile::Pure, "current-time"));
+    assert!(bound(Profile::NoIo, "current-time"));
+    assert!(!bound(Profile::NoIo, "file-exists?"));
+    assert!(bound(Profile::ReadOnly(root.clone()), "file-exists?"));
+    assert!(!bound(Profile::ReadOnly(root.clone()), "delete-file"));
+    assert!(!bound(Profile::ReadOnly(root.clone()), "system"));
+    assert!(bound(Profile::Full, "system"));
+
+    let mut interp = Interpreter::with_profile(Profile::Pure);
+    assert_eq!(interp.eval_str("(system \"true\")"), Err(Error::Unbound(Symbol::intern("system"))));
+
+    fs::write(root.join("mlisp_profile_test.txt"), "").unwrap();
+    let mut interp = Interpreter::with_profile(Profile::ReadOnly(root.clone()));
+    assert_eq!(interp.eval_str("(file-exists? \"mlisp_profile_test.txt\")"), Ok(Value::Bool(true)));
+    assert!(interp.eval_str("(file-exists? \"../mlisp_profile_test.txt\")").is_err());
+    let mut interp = Interpreter::new();
+    let path = root.join("mlisp_profile_test.txt");
+    assert_eq!(interp.eval_str(&format!("(delete-file {:?})", path)), Ok(Value::Unit));
+    assert!(!path.exists());
+}
+
+#[test]
+fn console_test() {
+    let mut interp = Interpreter::new();
+    let out = Port::string_output();
+    interp.set_output(Rc::clone(&out));
+    interp.set_input(Port::from_string("a line\n(1 \"two\")\n"));
+    interp.eval_str("(display \"hi\") (newline) (write \"hi\") (newline)
+                     (display '(1 \"a\" b)) (write '(1 \"a\" b)) (newline)
+                     (println 1 \"two\" 'three) (print)").unwrap();
+    assert_eq!(out.contents(), Some(String::from("hi\n\"hi\"\n(1 a b)(1 \"a\" b)\n1 two three\n")));
+
+    assert_eq!(interp.eval_str("(read-line)"), Ok(Value::Str("a line".into())));
+    assert_eq!(interp.eval_str("(car (cdr (read)))"), Ok(Value::Str("two".into())));
+    assert_eq!(interp.eval_str("(eof-object? (read))"), Ok(Value::Bool(true)));
+    assert_eq!(interp.eval_str("(eof-object? (read-line))"), Ok(Value::Bool(true)));
+
+    assert_eq!(Interpreter::with_profile(Profile::NoIo).get("display"), None);
+}
+
+#[test]
+fn ports_test() {
+    let mut interp = Interpreter::new();
+    assert_eq!(interp.eval_str("(define p (open-input-string \"ab\\ncd\"))
+                                (list (peek-char p) (read-char p) (read-char p) (read-line p) (read-line p))"),
+               interp.eval_str("'(\"a\" \"a\" \"b\" \"\" \"cd\")"));
+    assert_eq!(interp.eval_str("(eof-object? (read-char p))"), Ok(Value::Bool(true)));
+    assert_eq!(interp.eval_str("(with-output-to-string (lambda () (display 1) (write-string \"x\") (write \"y\")))"),
+               Ok(Value::Str("1x\"y\"".into())));
+    assert_eq!(interp.eval_str("(define o (open-output-string)) (write 'a o) (newline o) (get-output-string o)"),
+               Ok(Value::Str("a\n".into())));
+    assert_eq!(interp.eval_str("(list (input-port? p) (output-port? o) (port? 1))"),
+               interp.eval_str("'(#t #t #f)"));
+    assert_eq!(interp.eval_str("(close-port p) p").map(|v| v.to_string()), Ok(String::from("#<port string>")));
+    assert!(interp.eval_str("(read-char p)").is_err());
+
+    let path = ::std::env::temp_dir().join("mlisp_ports_test.txt");
+    let name = format!("{:?}", path.to_str().unwrap());
+    assert_eq!(interp.eval_str(&format!("(call-with-output-file {} (lambda (out) (write '(1 2) out) 'done))", name)),
+               interp.eval_str("'done"));
+    assert_eq!(interp.eval_str(&format!("(read (open-input-file {}))", name)), interp.eval_str("'(1 2)"));
+
+    let mut sandboxed = Interpreter::with_profile(Profile::ReadOnly(::std::env::temp_dir()));
+    assert_eq!(sandboxed.eval_str("(read (open-input-file \"mlisp_ports_test.txt\"))"), interp.eval_str("'(1 2)"));
+    assert!(sandboxed.eval_str("(open-input-file \"../mlisp_ports_test.txt\")").is_err());
+    assert_eq!(sandboxed.get("open-output-file"), None);
+    fs::remove_file(&path).unwrap();
+
+    let mut pure = Interpreter::with_profile(Profile::Pure);
+    assert_eq!(pure.eval_str("(with-output-to-string (lambda () (write-string \"x\")))"), Ok(Value::Str("x".into())));
+    assert_eq!(pure.eval_str("(eof-object? (read-char))"), Ok(Value::Bool(true)));
+    assert_eq!(pure.get("open-input-file"), None);
+}
+
+#[test]
+fn load_test() {
+    let dir = ::std::env::temp_dir().join("mlisp_load_test");
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("main.lisp"), "(load \"lib/utils.lisp\")\n(double 21)").unwrap();
+    fs::write(dir.join("lib/utils.lisp"), "(load \"more.lisp\")\n(define (double x) (twice x))").unwrap();
+    fs::write(dir.join("lib/more.lisp"), "(define (twice x) (* 2 x))").unwrap();
+    fs::write(dir.join("broken.lisp"), "(define a 1)\n\n(car\n  a)").unwrap();
+    fs::write(dir.join("cycle.lisp"), "(load \"cycle.lisp\")").unwrap();
+
+    let mut interp = Interpreter::new();
+    assert_eq!(interp.eval_file(dir.join("main.lisp")), Ok(Value::Int(42)));
+    assert_eq!(interp.eval_str("(twice 4)"), Ok(Value::Int(8)));
+
+    let broken = dir.join("broken.lisp");
+    let err = interp.eval_str(&format!("(load {:?})", broken.to_str().unwrap())).unwrap_err();
+    assert_eq!(err, Error::Located { file: broken.display().to_string(), line: 3,
+                                     error: Box::new(Error::Type(String::from("expected pair, got 1"))) });
+    assert_eq!(err.root(), &Error::Type(String::from("expected pair, got 1")));
+
+    let err = interp.eval_file(dir.join("cycle.lisp")).unwrap_err();
+    assert!(err.to_string().contains("load cycle:"), "{}", err);
+
+    let mut sandboxed = Interpreter::with_profile(Profile::ReadOnly(dir.clone()));
+    assert_eq!(sandboxed.eval_str("(load \"lib/utils.lisp\") (double 5)"), Ok(Value::Int(10)));
+    assert!(sandboxed.eval_str("(load \"../x.lisp\")").is_err());
+    assert_eq!(Interpreter::with_profile(Profile::NoIo).get("load"), None);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,40 @@
+#[allow(dead_code)]
+mod parser_combinators;
+mod parser;
+mod symbol;
+mod error;
+mod value;
+mod env;
+mod compile;
+mod builtins;
+mod context;
+mod continuation;
+mod convert;
+mod foreign;
+mod generator;
+mod hash_table;
+mod expr;
+mod eval;
+mod exception;
+mod interpreter;
+mod module;
+mod pattern;
+mod persistent;
+mod port;
+mod promise;
+mod record;
+mod sandbox;
+mod system;
+mod vector;
+
+pub use builtins::{Arity, Builtin};
+pub use context::Limits;
+pub use convert::{FromLisp, IntoLispResult, ToLisp, TypedFn};
+pub use error::Error;
+pub use foreign::{Foreign, ForeignType, Handle};
+pub use interpreter::Interpreter;
+pub use port::Port;
+pub use record::{Record, RecordType};
+pub use sandbox::{Group, Profile};
+pub use symbol::Symbol;
+pub use value::Value;
@@ -1,34 +1,38 @@
-#![feature(slice_patterns)]
+extern crate mlisp;
 
-mod parser_combinators;
-mod parser;
-mod expr;
-mod eval;
+use std::env;
 use std::ffi::CStr;
 use std::ffi::CString;
+use std::io::{self, Read};
 use std::os::raw::c_char;
+use std::process;
+use std::rc::Rc;
+use std::time::Duration;
+use mlisp::{Interpreter, Limits, Port, Profile};
 
 fn main() {
-    fn s(txt: &str) -> Result<expr::Expr, &str> {
-        parser::parse(&txt.chars().collect::<Vec<char>>())
-            .map(|x| eval::eval(&x.res).map(|(x,_)| x))
-            .unwrap()
-    }
-
-    println!("{:?}", (s("(define sub1 (lambda (z) (- z 1))) (define sub2 (z) (- z 2))
-(define or (a b) (if a true (if b true false)))
-
-(define fib (a)
-  (if (or (eq? a 1) (eq? a 2))
-    1
-    (+ (fib (sub1 a)) (fib (sub2 a)))))
+    let (flags, files): (Vec<_>, Vec<_>) = env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let mut interp = if flags.iter().any(|f| f == "--no-prelude") {
+        Interpreter::bare(Profile::Full)
+    } else {
+        Interpreter::new()
+    };
+    let result = if files.is_empty() {
+        let mut src = String::new();
+        io::stdin().read_to_string(&mut src)
+            .map_err(|e| mlisp::Error::Io(e.to_string()))
+            .and_then(|_| interp.eval_str(&src))
+    } else {
+        files.iter().try_fold(mlisp::Value::Unit, |_, file| interp.eval_file(file))
+    };
 
-(define downto (from f)
-  (if (eq? 1 from) 
-      (f from)
-      (append  (f from) (downto (sub1 from) f))))
-
-(downto 4 fib)")));
+    match result {
+        Ok(value) => println!("{}", value),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 }
 
 
@@ -42,12 +46,40 @@ fn to_c_str(s: &str) -> *mut c_char {
         .into_raw()
 }
 
+/// What one run of the browser demo may use, so that a runaway program
+/// reports an error rather than hanging the page.
+const DEMO_LIMITS: Limits = Limits {
+    fuel: Some(10_000_000),
+    max_depth: None,
+    max_cells: Some(1_000_000),
+    timeout: Some(Duration::from_secs(5)),
+};
+
+/// Runs `code` for the browser demo, returning what it printed and its
+/// value.
+fn run_demo(code: &str) -> (String, Result<mlisp::Value, mlisp::Error>) {
+    let mut interp = Interpreter::new();
+    interp.set_limits(DEMO_LIMITS);
+    let printed = Port::string_output();
+    interp.set_output(Rc::clone(&printed));
+    interp.set_input(Port::from_string(""));
+    let result = interp.eval_str(code);
+    (printed.contents().unwrap_or_default(), result)
+}
+
 #[no_mangle]
 pub fn js_run_code(code: *mut c_char) -> *mut c_char {
-    let s = from_c_str(code).chars().collect::<Vec<char>>();
-    let output = 
-        parser::parse(&s).map(|x| eval::eval(&x.res).map(|(x,_)| format!("{}", x)))
-        .unwrap_or_else(|_| Ok(String::from("parsing error.")))
-        .unwrap_or_else(String::from);
-    to_c_str(&output)
+    let (printed, result) = run_demo(&from_c_str(code));
+    let result = result
+        .map(|x| format!("{}", x))
+        .unwrap_or_else(|e| e.to_string());
+    to_c_str(&(printed + &result))
+}
+
+#[test]
+fn demo_test() {
+    assert_eq!(run_demo("(display \"hi \") (+ 1 2)"), (String::from("hi "), Ok(mlisp::Value::Int(3))));
+    for runaway in &["(define (f x) (f x)) (f 1)", "(define (grow xs) (grow (cons 1 xs))) (grow '())"] {
+        assert!(run_demo(runaway).1.unwrap_err().is_resource_limit());
+    }
 }
@@ -0,0 +1,331 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use compile::{symbols, syntax_error};
+use context::Context;
+use env::{Env, Global};
+use error::Error;
+use eval::eval;
+use expr::Expr;
+use sandbox::Fs;
+use symbol::Symbol;
+use system::load;
+use value::Value;
+
+/// A module as written: its name, what it exports and the forms of its
+/// body, which are evaluated in an environment of their own.
+pub struct ModuleDef {
+    pub name: String,
+    /// Pairs of the name inside the module and the name it is exported as.
+    pub exports: Vec<(Symbol, Symbol)>,
+    pub body: Vec<Expr>,
+}
+
+/// A module once its body has run: the cells of its exports.
+pub struct Module {
+    exports: Vec<(Symbol, Rc<Global>)>,
+}
+
+/// Which bindings of a module an `import` brings in, and under what names.
+pub enum ImportSet {
+    Module(String),
+    Only(Box<ImportSet>, Vec<Symbol>),
+    Except(Box<ImportSet>, Vec<Symbol>),
+    Prefix(Box<ImportSet>, Symbol),
+    Rename(Box<ImportSet>, Vec<(Symbol, Symbol)>),
+}
+
+/// The modules of one interpreter, each defined or loaded at most once.
+pub struct Modules {
+    loaded: HashMap<String, Rc<Module>>,
+    /// What every module starts out with: the builtins.
+    pub base: Env,
+    /// Directories searched for module files, after the directory of the
+    /// file being loaded.
+    pub path: Vec<PathBuf>,
+    /// How module files are found, or `None` if the sandbox forbids
+    /// reading them.
+    fs: Option<Fs>,
+}
+
+impl Modules {
+    pub fn new(base: Env, fs: Option<Fs>) -> Modules {
+        Modules { loaded: HashMap::new(), base, path: vec![PathBuf::from(".")], fs }
+    }
+}
+
+fn sym(name: &str) -> Expr {
+    Expr::Symbol(Symbol::intern(name))
+}
+
+/// `utils` is named `utils`, and `(utils math)` is named `utils/math`,
+/// which is also where it is looked for.
+fn module_name(expr: &Expr) -> Result<String, Error> {
+    let parts = match *expr {
+        Expr::Symbol(name) => return Ok(name.name().to_string()),
+        Expr::List(ref parts) if !parts.is_empty() => parts,
+        ref x => return Err(Error::Syntax(format!("bad module name {}", x))),
+    };
+    let parts = parts.iter()
+        .map(|part| match *part {
+            Expr::Symbol(name) => Ok(name.name().to_string()),
+            Expr::Int(i) => Ok(i.to_string()),
+            ref x => Err(Error::Syntax(format!("bad module name part {}", x))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.join("/"))
+}
+
+/// The names in an `(export ...)` form: `name` or `(rename inner outer)`.
+fn exports(items: &[Expr]) -> Result<Vec<(Symbol, Symbol)>, Error> {
+    items[1..].iter()
+        .map(|spec| match *spec {
+            Expr::Symbol(name) => Ok((name, name)),
+            Expr::List(ref rename) if rename.len() == 3 && rename[0] == sym("rename") => {
+                let names = symbols(&rename[1..])?;
+                Ok((names[0], names[1]))
+            }
+            ref x => Err(Error::Syntax(format!("bad export {}", x))),
+        })
+        .collect()
+}
+
+fn clause<'a>(expr: &'a Expr, keyword: &str) -> Option<&'a [Expr]> {
+    match *expr {
+        Expr::List(ref items) if items.first() == Some(&sym(keyword)) => Some(items),
+        _ => None,
+    }
+}
+
+impl ModuleDef {
+    /// `(module name (export ...) body...)`
+    pub fn parse_module(items: &[Expr]) -> Result<ModuleDef, Error> {
+        match *items {
+            [_, ref name, ref export, ref body @ ..] => match clause(export, "export") {
+                Some(export) => Ok(ModuleDef {
+                    name: module_name(name)?,
+                    exports: exports(export)?,
+                    body: body.to_vec(),
+                }),
+                None => syntax_error("module expects an export list", items),
+            },
+            _ => syntax_error("module expects a name and an export list", items),
+        }
+    }
+
+    /// `(define-library name decl...)`, where each declaration is an
+    /// `(export ...)`, an `(import ...)` or a `(begin ...)`.
+    pub fn parse_library(items: &[Expr]) -> Result<ModuleDef, Error> {
+        let (name, decls) = match *items {
+            [_, ref name, ref decls @ ..] => (module_name(name)?, decls),
+            _ => return syntax_error("define-library expects a name", items),
+        };
+        let mut def = ModuleDef { name, exports: Vec::new(), body: Vec::new() };
+        for decl in decls {
+            if let Some(export) = clause(decl, "export") {
+                def.exports.extend(exports(export)?);
+            } else if clause(decl, "import").is_some() {
+                def.body.push(decl.clone());
+            } else if let Some(begin) = clause(decl, "begin") {
+                def.body.extend(begin[1..].iter().cloned());
+            } else {
+                return Err(Error::Syntax(format!("bad library declaration {}", decl)));
+            }
+        }
+        Ok(def)
+    }
+}
+
+impl ImportSet {
+    pub fn parse_all(sets: &[Expr]) -> Result<Vec<ImportSet>, Error> {
+        sets.iter().map(ImportSet::parse).collect()
+    }
+
+    fn parse(set: &Expr) -> Result<ImportSet, Error> {
+        let items = match *set {
+            Expr::List(ref items) if items.len() >= 2 => items,
+            _ => return Ok(ImportSet::Module(module_name(set)?)),
+        };
+        let inner = || ImportSet::parse(&items[1]).map(Box::new);
+        match items[0] {
+            Expr::Symbol(s) if &*s.name() == "only" => Ok(ImportSet::Only(inner()?, symbols(&items[2..])?)),
+            Expr::Symbol(s) if &*s.name() == "except" => Ok(ImportSet::Except(inner()?, symbols(&items[2..])?)),
+            Expr::Symbol(s) if &*s.name() == "prefix" => match symbols(&items[2..])?[..] {
+                [prefix] => Ok(ImportSet::Prefix(inner()?, prefix)),
+                _ => syntax_error("prefix expects one prefix", items),
+            },
+            Expr::Symbol(s) if &*s.name() == "rename" => {
+                let renames = items[2..].iter()
+                    .map(|pair| match *pair {
+                        Expr::List(ref pair) if pair.len() == 2 => symbols(pair).map(|p| (p[0], p[1])),
+                        ref x => Err(Error::Syntax(format!("bad rename {}", x))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(ImportSet::Rename(inner()?, renames))
+            }
+            _ => Ok(ImportSet::Module(module_name(set)?)),
+        }
+    }
+
+    fn module(&self) -> &str {
+        match *self {
+            ImportSet::Module(ref name) => name,
+            ImportSet::Only(ref inner, _) | ImportSet::Except(ref inner, _)
+            | ImportSet::Prefix(ref inner, _) | ImportSet::Rename(ref inner, _) => inner.module(),
+        }
+    }
+}
+
+/// Runs the body of a module in a fresh environment holding only the
+/// builtins, and registers the module under its name.
+pub fn define(def: &ModuleDef, ctx: &Context) -> Result<Value, Error> {
+    let env = Rc::new(RefCell::new(ctx.modules.borrow().base.fork()));
+    for expr in &def.body {
+        eval(expr, &env, ctx)?;
+    }
+    let mut exports = Vec::new();
+    for &(inner, outer) in &def.exports {
+        let cell = env.borrow_mut().cell(inner);
+        if cell.get().is_none() {
+            return Err(Error::Syntax(format!("module {} exports {}, which it does not define", def.name, inner)));
+        }
+        exports.push((outer, cell));
+    }
+    let module = Module { exports };
+    ctx.modules.borrow_mut().loaded.insert(def.name.clone(), Rc::new(module));
+    Ok(Value::Unit)
+}
+
+/// Binds the names selected by `sets` in the current global environment.
+/// They forward to the module's cells, so they see later changes the
+/// module makes to them, while a `define` of one of them shadows the
+/// import without touching the module.
+pub fn import(sets: &[ImportSet], ctx: &Context) -> Result<Value, Error> {
+    for set in sets {
+        let bindings = resolve(set, ctx)?;
+        let env = ctx.env();
+        for (name, cell) in bindings {
+            env.borrow_mut().alias(name, cell);
+        }
+    }
+    Ok(Value::Unit)
+}
+
+fn resolve(set: &ImportSet, ctx: &Context) -> Result<Vec<(Symbol, Rc<Global>)>, Error> {
+    fn check(bindings: &[(Symbol, Rc<Global>)], names: &[Symbol], set: &ImportSet) -> Result<(), Error> {
+        match names.iter().find(|&&n| !bindings.iter().any(|b| b.0 == n)) {
+            Some(name) => Err(Error::Syntax(format!("{} does not export {}", set.module(), name))),
+            None => Ok(()),
+        }
+    }
+    match *set {
+        ImportSet::Module(ref name) => Ok(find(name, ctx)?.exports.clone()),
+        ImportSet::Only(ref inner, ref names) => {
+            let bindings = resolve(inner, ctx)?;
+            check(&bindings, names, set)?;
+            Ok(bindings.into_iter().filter(|b| names.contains(&b.0)).collect())
+        }
+        ImportSet::Except(ref inner, ref names) => {
+            let bindings = resolve(inner, ctx)?;
+            check(&bindings, names, set)?;
+            Ok(bindings.into_iter().filter(|b| !names.contains(&b.0)).collect())
+        }
+        ImportSet::Prefix(ref inner, prefix) => Ok(resolve(inner, ctx)?.into_iter()
+            .map(|(name, cell)| (Symbol::intern(&format!("{}{}", prefix, name)), cell))
+            .collect()),
+        ImportSet::Rename(ref inner, ref renames) => {
+            let bindings = resolve(inner, ctx)?;
+            check(&bindings, &renames.iter().map(|r| r.0).collect::<Vec<_>>(), set)?;
+            Ok(bindings.into_iter()
+                .map(|(name, cell)| match renames.iter().find(|r| r.0 == name) {
+                    Some(&(_, new)) => (new, cell),
+                    None => (name, cell),
+                })
+                .collect())
+        }
+    }
+}
+
+/// The module called `name`, loading it from `name.lisp` on the search path
+/// the first time it is asked for. The file is evaluated in an environment
+/// of its own and has to define the module.
+fn find(name: &str, ctx: &Context) -> Result<Rc<Module>, Error> {
+    if let Some(module) = ctx.modules.borrow().loaded.get(name) {
+        return Ok(Rc::clone(module));
+    }
+    let path = locate(name, ctx)?;
+    let env = Rc::new(RefCell::new(ctx.modules.borrow().base.fork()));
+    ctx.with_env(&env, || load(&path, ctx))?;
+    match ctx.modules.borrow().loaded.get(name) {
+        Some(module) => Ok(Rc::clone(module)),
+        None => Err(Error::Io(format!("{} does not define module {}", path.display(), name))),
+    }
+}
+
+fn locate(name: &str, ctx: &Context) -> Result<PathBuf, Error> {
+    let modules = ctx.modules.borrow();
+    let not_found = || Error::Io(format!("cannot find module {}", name));
+    let fs = modules.fs.as_ref().ok_or_else(not_found)?;
+    let file = format!("{}.lisp", name);
+    let here = ctx.loading.borrow().last().and_then(|p| p.parent().map(PathBuf::from));
+    here.iter().chain(&modules.path)
+        .filter_map(|dir| fs.resolve(dir.join(&file)).ok())
+        .find(|path| path.is_file())
+        .ok_or_else(not_found)
+}
+
+#[test]
+fn modules_test() {
+    use std::fs;
+    use interpreter::Interpreter;
+    use port::Port;
+    use sandbox::Profile;
+
+    let mut interp = Interpreter::new();
+    interp.eval_str("(module utils (export sum (rename count-up next!))
+                       (define total 0)
+                       (define (sum xs) (if (null? xs) 0 (+ (car xs) (sum (cdr xs)))))
+                       (define (count-up) (set! total (+ total 1)) total))").unwrap();
+    assert_eq!(interp.eval_str("(import utils) (sum '(1 2 3))"), Ok(Value::Int(6)));
+    assert_eq!(interp.eval_str("(next!) (next!)"), Ok(Value::Int(2)));
+    assert_eq!(interp.eval_str("total"), Err(Error::Unbound(Symbol::intern("total"))));
+    assert_eq!(interp.eval_str("(import (prefix (only utils sum) u:)) (u:sum '(4 5))"), Ok(Value::Int(9)));
+    assert_eq!(interp.eval_str("(import (rename utils (sum add-all))) (add-all '(1 1))"), Ok(Value::Int(2)));
+    assert!(interp.eval_str("(import (except utils sum)) (next!)").is_ok());
+    assert_eq!(interp.eval_str("(import (only utils nope))"),
+               Err(Error::Syntax(String::from("utils does not export nope"))));
+    assert!(interp.eval_str("(module bad (export missing) (define x 1))").is_err());
+    assert!(interp.eval_str("(define (f) (import utils))").is_err());
+    assert_eq!(interp.eval_str("(define (sum xs) 'mine) (sum '(1 2))"), Ok(Value::Symbol(Symbol::intern("mine"))));
+    assert_eq!(interp.eval_str("(u:sum '(4 5))"), Ok(Value::Int(9)));
+
+    let mut late = Interpreter::new();
+    late.eval_str("(define (f) (sum '(1 2)))").unwrap();
+    late.eval_str("(module utils (export sum) (define (sum xs) (apply + xs)))").unwrap();
+    assert_eq!(late.eval_str("(import utils) (f)"), Ok(Value::Int(3)));
+
+    interp.eval_str("(define-library (shapes square)
+                       (export area)
+                       (import (only utils sum))
+                       (begin (define (area s) (sum (list s s s s)))))").unwrap();
+    assert_eq!(interp.eval_str("(import (shapes square)) (area 2)"), Ok(Value::Int(8)));
+
+    let dir = ::std::env::temp_dir().join("mlisp_modules_test");
+    fs::create_dir_all(dir.join("geo")).unwrap();
+    fs::write(dir.join("geo/point.lisp"), "(display \"loading \")
+        (define-library (geo point) (export make-point px)
+          (begin (define (make-point x y) (cons x y)) (define px car)))").unwrap();
+    let out = Port::string_output();
+    interp.set_output(Rc::clone(&out));
+    interp.add_module_path(&dir);
+    assert_eq!(interp.eval_str("(import (geo point)) (px (make-point 3 4))"), Ok(Value::Int(3)));
+    assert_eq!(interp.eval_str("(import (geo point)) (px (make-point 5 6))"), Ok(Value::Int(5)));
+    assert_eq!(out.contents(), Some(String::from("loading ")));
+    assert!(interp.eval_str("(import nowhere)").is_err());
+
+    let mut pure = Interpreter::with_profile(Profile::Pure);
+    pure.add_module_path(&dir);
+    assert_eq!(pure.eval_str("(import (geo point))"), Err(Error::Io(String::from("cannot find module geo/point"))));
+    fs::remove_dir_all(&dir).unwrap();
+}
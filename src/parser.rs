@@ -1,33 +1,284 @@
 use parser_combinators::*;
+use error::Error;
 use expr::Expr;
+use symbol::{kw, Symbol};
 
-pub fn parse(txt: &[char]) -> ParseResult<Vec<Expr>> {
-    let quote_mark = p_char('"');
-    let expr = refl_parser(|expr| {
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "()\";'`,".contains(c)
+}
+
+fn atom(token: String) -> Expr {
+    match token.as_str() {
+        "#t" | "#true" => return Expr::Bool(true),
+        "#f" | "#false" => return Expr::Bool(false),
+        "+inf.0" => return Expr::Float(f64::INFINITY),
+        "-inf.0" => return Expr::Float(f64::NEG_INFINITY),
+        "+nan.0" => return Expr::Float(f64::NAN),
+        _ => {}
+    }
+    let numeric = token
+        .trim_start_matches(['+', '-'])
+        .starts_with(|c: char| c.is_ascii_digit());
+    if numeric {
+        if let Ok(i) = token.parse::<i64>() {
+            return Expr::Int(i);
+        }
+        if let Ok(f) = token.parse::<f64>() {
+            return Expr::Float(f);
+        }
+    }
+    Expr::Symbol(Symbol::intern(&token))
+}
+
+fn unescape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        c => c,
+    }
+}
+
+fn list(mut items: Vec<Expr>) -> Expr {
+    let n = items.len();
+    if n >= 3 && items[n - 2] == Expr::Symbol(Symbol::intern(".")) {
+        let tail = items.pop().unwrap();
+        items.pop();
+        Expr::DottedList(items, Box::new(tail))
+    } else {
+        Expr::List(items)
+    }
+}
+
+fn blank<'a>() -> RcParser<'a, Vec<usize>> {
+    let comment = p_char(';').right(many(p_satisfy(|c| c != '\n')));
+    spaces().right(many(comment.right(spaces())))
+}
+
+/// A single datum, with any blanks and comments before it.
+fn datum<'a>() -> RcParser<'a, Expr> {
+    let string_char = any(vec![
+        p_char('\\').right(p_satisfy(|_| true)).map(unescape),
+        p_satisfy(|c| c != '"' && c != '\\'),
+    ]);
+
+    let list_end = blank().left(p_char(')'));
+    let item = blank();
+    refl_parser(move |expr| {
         let expr_impl = vec![
-            p_int().map(Expr::Int),
-            quote_mark.right(p_string().map(Expr::Str)).left(
-                quote_mark
-            ),
+            p_char('"')
+                .right(many(string_char))
+                .left(p_char('"'))
+                .map(|s| Expr::Str(s.into_iter().collect())),
 
-            p_char('\'').right(p_string()).map(Expr::Symbol),
-            p_string().map(Expr::Ident),
+            p_char('\'').right(expr.clone()).map(|e| Expr::List(vec![Expr::Symbol(kw::QUOTE), e])),
+            p_char('`').right(expr.clone()).map(|e| Expr::List(vec![Expr::Symbol(kw::QUASIQUOTE), e])),
+            p_str(",@").right(expr.clone()).map(|e| Expr::List(vec![Expr::Symbol(kw::UNQUOTE_SPLICING), e])),
+            p_char(',').right(expr.clone()).map(|e| Expr::List(vec![Expr::Symbol(kw::UNQUOTE), e])),
 
             p_char('(')
-                .right(spaces().right(p_string().left(spaces())))
-                .both(all(spaces().right(expr).left(spaces())))
-                .map(|(hd, tl)| Expr::List(hd, tl))
-                .left(p_char(')')),
+                .right(many(expr.clone()))
+                .left(list_end.clone())
+                .map(list),
 
-            p_char('(')
-                .right(spaces().right(p_string().left(spaces())))
-                .map(|hd| Expr::List(hd, Vec::new()))
-                .left(p_char(')')),
+            p_str("#(")
+                .right(many(expr.clone()))
+                .left(list_end.clone())
+                .map(Expr::Vector),
+
+            p_str("#hash(")
+                .right(many(blank()
+                    .right(p_char('('))
+                    .right(expr.both(blank().right(p_char('.')).right(expr.clone())))
+                    .left(list_end.clone())))
+                .left(list_end)
+                .map(Expr::Hash),
+
+            all(p_satisfy(|c| !is_delimiter(c)))
+                .map(|s| atom(s.into_iter().collect())),
         ];
 
+        item.right(any(expr_impl))
+    })
+}
+
+/// How deeply lists, vectors and quotes may nest. The reader and the
+/// compiler take native stack for each level, and a lot more of it in
+/// unoptimised builds.
+pub const MAX_NESTING: usize = if cfg!(debug_assertions) { 200 } else { 1000 };
+
+/// Fails if the data in `txt` nest deeper than `MAX_NESTING`, before the
+/// recursive reader gets to overflow the stack on them.
+fn check_nesting(txt: &[char]) -> Result<(), Error> {
+    // An entry for each open list, vector or hash table (`false`), and for
+    // each quote still waiting for its datum (`true`).
+    let mut open = Vec::new();
+    let mut i = 0;
+    while i < txt.len() {
+        let complete = match txt[i] {
+            ';' => {
+                while i < txt.len() && txt[i] != '\n' {
+                    i += 1;
+                }
+                false
+            }
+            '"' => {
+                i += 1;
+                while i < txt.len() && txt[i] != '"' {
+                    i += if txt[i] == '\\' { 2 } else { 1 };
+                }
+                true
+            }
+            '(' => {
+                open.push(false);
+                false
+            }
+            ')' => {
+                open.pop();
+                true
+            }
+            '\'' | '`' | ',' => {
+                if txt[i] == ',' && txt.get(i + 1) == Some(&'@') {
+                    i += 1;
+                }
+                open.push(true);
+                false
+            }
+            c if c.is_whitespace() => false,
+            _ => {
+                while i + 1 < txt.len() && !is_delimiter(txt[i + 1]) {
+                    i += 1;
+                }
+                // `#(` and `#hash(` open a vector or hash table.
+                txt.get(i + 1) != Some(&'(')
+            }
+        };
+        if complete {
+            while open.last() == Some(&true) {
+                open.pop();
+            }
+        }
+        if open.len() > MAX_NESTING {
+            let line = txt[..i].iter().filter(|&&c| c == '\n').count() + 1;
+            return Err(Error::Parse(format!("data nested more than {} deep at line {}", MAX_NESTING, line)));
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+pub fn parse(txt: &[char]) -> ParseResult<'_, Vec<Expr>> {
+    many(datum()).left(blank()).parse(txt)
+}
+
+/// Reads the first datum in `src`, returning it with the number of bytes it
+/// took up, or `None` if there is nothing but blanks and comments.
+pub fn read_datum(src: &str) -> Result<Option<(Expr, usize)>, Error> {
+    let txt = src.chars().collect::<Vec<char>>();
+    check_nesting(&txt)?;
+    let datum = datum();
+    let blank = blank();
+    match datum.parse(&txt) {
+        Ok(corr) => {
+            let used = txt.len() - corr.txt.len();
+            let bytes = src.char_indices().nth(used).map_or(src.len(), |(i, _)| i);
+            Ok(Some((corr.res, bytes)))
+        }
+        Err(_) => {
+            let rest = blank.parse(&txt).map_or(&txt[..], |corr| corr.txt);
+            if rest.is_empty() { Ok(None) } else { Err(unreadable(&txt, rest)) }
+        }
+    }
+}
+
+fn unreadable(txt: &[char], rest: &[char]) -> Error {
+    let line = txt[..txt.len() - rest.len()].iter().filter(|&&c| c == '\n').count() + 1;
+    let snippet = rest.iter().take(20).collect::<String>();
+    Error::Parse(format!("unreadable input at line {}: {}", line, snippet))
+}
+
+/// Parses a whole program, failing if any of it cannot be read.
+pub fn read(src: &str) -> Result<Vec<Expr>, Error> {
+    let txt = src.chars().collect::<Vec<char>>();
+    check_nesting(&txt)?;
+    let rest = match parse(&txt) {
+        Ok(ref corr) if corr.txt.is_empty() => return Ok(corr.res.clone()),
+        Ok(corr) => corr.txt,
+        Err((_, rest)) => rest,
+    };
+    Err(unreadable(&txt, rest))
+}
+
+/// Like `read`, also giving the line each datum starts on.
+pub fn read_lines(src: &str) -> Result<Vec<(usize, Expr)>, Error> {
+    let txt = src.chars().collect::<Vec<char>>();
+    check_nesting(&txt)?;
+    let datum = datum();
+    let blank = blank();
+    let mut data = Vec::new();
+    let mut rest = &txt[..];
+    let mut line = 1;
+    loop {
+        let start = blank.parse(rest).map_or(rest, |corr| corr.txt);
+        line += rest[..rest.len() - start.len()].iter().filter(|&&c| c == '\n').count();
+        if start.is_empty() {
+            return Ok(data);
+        }
+        match datum.parse(start) {
+            Ok(corr) => {
+                data.push((line, corr.res));
+                line += start[..start.len() - corr.txt.len()].iter().filter(|&&c| c == '\n').count();
+                rest = corr.txt;
+            }
+            Err(_) => return Err(unreadable(&txt, start)),
+        }
+    }
+}
+
+#[test]
+fn parse_test() {
+    fn p(txt: &str) -> Vec<Expr> {
+        parse(&txt.chars().collect::<Vec<char>>()).unwrap().res
+    }
+    fn sym(name: &str) -> Expr {
+        Expr::Symbol(Symbol::intern(name))
+    }
+    assert_eq!(p("42 -7 1.5 #t #f"), vec![Expr::Int(42), Expr::Int(-7), Expr::Float(1.5),
+                                         Expr::Bool(true), Expr::Bool(false)]);
+    assert_eq!(p("- -> vector-set!"), vec![sym("-"), sym("->"), sym("vector-set!")]);
+    assert_eq!(p("\"a \\\"b\\\"\\n\""), vec![Expr::Str(String::from("a \"b\"\n"))]);
+    assert_eq!(p("(a (b) ())"), vec![Expr::List(vec![sym("a"), Expr::List(vec![sym("b")]),
+                                                      Expr::List(vec![])])]);
+    assert_eq!(p("#(1 #(a) ())"), vec![Expr::Vector(vec![Expr::Int(1), Expr::Vector(vec![sym("a")]),
+                                                          Expr::List(vec![])])]);
+    assert_eq!(p("#hash((a . 1) ( \"b\" . (2)))"), vec![Expr::Hash(vec![(sym("a"), Expr::Int(1)),
+        (Expr::Str(String::from("b")), Expr::List(vec![Expr::Int(2)]))])]);
+    assert_eq!(p("(a b . c)"), vec![Expr::DottedList(vec![sym("a"), sym("b")], Box::new(sym("c")))]);
+    assert_eq!(p("'x ; comment\n 1"), vec![Expr::List(vec![sym("quote"), sym("x")]), Expr::Int(1)]);
+    assert_eq!(p("`(a ,b ,@c)"), vec![Expr::List(vec![sym("quasiquote"), Expr::List(vec![sym("a"),
+        Expr::List(vec![sym("unquote"), sym("b")]), Expr::List(vec![sym("unquote-splicing"), sym("c")])])])]);
+    assert_eq!(p(" ; nothing"), vec![]);
+
+    assert_eq!(read("(+ 1 2)"), Ok(vec![Expr::List(vec![sym("+"), Expr::Int(1), Expr::Int(2)])]));
+    assert_eq!(read("1\n(+ 1 2"), Err(Error::Parse(String::from("unreadable input at line 2: (+ 1 2"))));
+    assert_eq!(read("1)"), Err(Error::Parse(String::from("unreadable input at line 1: )"))));
+
+    assert_eq!(read_datum(" 1 (2)"), Ok(Some((Expr::Int(1), 2))));
+    assert_eq!(read_datum("\"é\" 2"), Ok(Some((Expr::Str(String::from("é")), 4))));
+    assert_eq!(read_datum(" ; just a comment\n"), Ok(None));
+    assert!(read_datum("\n(1").is_err());
 
-        spaces().right(any(expr_impl).left(spaces()))
-    });
+    assert_eq!(read_lines("1 ; one\n\n(a\n b) 'c\n"),
+               Ok(vec![(1, Expr::Int(1)), (3, Expr::List(vec![sym("a"), sym("b")])),
+                       (4, Expr::List(vec![sym("quote"), sym("c")]))]));
+    assert_eq!(read_lines("1\n(+ 1 2"), Err(Error::Parse(String::from("unreadable input at line 2: (+ 1 2"))));
 
-    all(expr).parse(txt)
-}
\ No newline at end of file
+    let too_deep = Error::Parse(format!("data nested more than {} deep at line 1", MAX_NESTING));
+    assert_eq!(read(&"(".repeat(50000)), Err(too_deep.clone()));
+    assert_eq!(read(&format!("{}x", "'".repeat(50000))), Err(too_deep.clone()));
+    assert_eq!(read_datum(&format!("#({}", "#hash((a . ".repeat(50000))), Err(too_deep));
+    let deepest = format!("{}x{}", "(".repeat(MAX_NESTING), ")".repeat(MAX_NESTING));
+    assert!(read(&deepest).is_ok());
+    assert!(read(&format!("\"{}\" ; {}\n{}", "(".repeat(50000), "(".repeat(50000), deepest)).is_ok());
+}
@@ -6,7 +6,7 @@ use std::cell::RefCell;
 
 #[derive(Debug)]
 pub struct Corr<'a, T> {
-    txt: &'a [char],
+    pub txt: &'a [char],
     pub res: T,
 }
 
@@ -23,7 +23,7 @@ pub fn p_char<'a>(ch: char) -> RcParser<'a, char> {
     })
 }
 
-pub fn p_str<'a>(string: &'a str) -> RcParser<'a, &str> {
+pub fn p_str<'a>(string: &'a str) -> RcParser<'a, &'a str> {
     let s: Vec<char> = string.chars().collect();
     LambdaParser::create(move |txt| if txt.starts_with(&s) {
         let corr = Corr {
@@ -175,21 +175,32 @@ where
     x
 }
 
-pub fn p_string<'a>() -> RcParser<'a, String> {
-    let chars = ((b'*')..(b'z') + 1)
-        .map(|x| p_char(x as char).as_rc())
-        .collect::<Vec<_>>();
-
-    all(any(chars)).map(|x| x.into_iter().collect::<String>())
+pub fn p_satisfy<'a, F>(pred: F) -> RcParser<'a, char>
+where
+    F: Fn(char) -> bool + 'a,
+{
+    LambdaParser::create(move |txt| if !txt.is_empty() && pred(txt[0]) {
+        Ok(Corr {
+            txt: &txt[1..],
+            res: txt[0],
+        })
+    } else {
+        Err(("no matching char", txt))
+    })
 }
 
-pub fn p_int<'a>() -> RcParser<'a, i64> {
-    let chars = ((b'0')..(b'9') + 1)
-        .map(|x| p_char(x as char).as_rc())
-        .collect::<Vec<_>>();
-
-    all(any(chars)).map(|x| {
-        x.into_iter().collect::<String>().parse::<i64>().unwrap()
+pub fn many<'a, T>(parser: RcParser<'a, T>) -> RcParser<'a, Vec<T>>
+where
+    T: 'a,
+{
+    LambdaParser::create(move |txt| {
+        let mut res = Vec::new();
+        let mut txt = txt;
+        while let Ok(corr) = parser.parse(txt) {
+            res.push(corr.res);
+            txt = corr.txt;
+        }
+        Ok(Corr { res, txt })
     })
 }
 
@@ -207,7 +218,7 @@ pub fn spaces<'a>() -> RcParser<'a, usize> {
     })
 }
 
-pub type RcParser<'a, R> = Rc<Parser<'a, Return = R> + 'a>;
+pub type RcParser<'a, R> = Rc<dyn Parser<'a, Return = R> + 'a>;
 
 
 pub struct LambdaParser<'a, Out, T>
@@ -252,6 +263,7 @@ pub trait Parser<'a> {
     type Return;
     fn parse(&self, txt: &'a [char]) -> ParseResult<'a, Self::Return>;
 
+    #[allow(clippy::wrong_self_convention)]
     fn as_rc(self) -> RcParser<'a, Self::Return>;
 
     fn both<B, BRet>(&self, right: B) -> RcParser<'a, (Self::Return, BRet)>
@@ -0,0 +1,165 @@
+use std::rc::Rc;
+use builtins::type_error;
+use compile::Node;
+use context::Context;
+use env::Frame;
+use error::Error;
+use eval::{apply, eval_node};
+use value::Value;
+
+/// A pattern of a `match` clause, with the variables it binds numbered by
+/// their slot in the frame the clause body runs in.
+pub enum Pattern {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// The first occurrence of a variable.
+    Bind(usize),
+    /// A later occurrence of a variable, which matches a value `equal?` to
+    /// the one bound by the first.
+    Same(usize),
+    /// A number, string, boolean or quoted datum, compared with `equal?`.
+    Literal(Value),
+    /// A list of patterns, with a pattern for the tail if it is dotted.
+    List(Vec<Pattern>, Option<Box<Pattern>>),
+    Vector(Vec<Pattern>),
+    /// `(? pred pat...)`: values `pred` holds for that match every pattern.
+    Pred(Rc<Node>, Vec<Pattern>),
+    /// `($ type pat...)`: records of `type` whose first fields match the
+    /// patterns.
+    Record(Rc<Node>, Vec<Pattern>),
+}
+
+/// `(pattern [(when guard)] body...)`.
+pub struct Clause {
+    pub pattern: Pattern,
+    pub frame_size: usize,
+    pub guard: Option<Rc<Node>>,
+    pub body: Rc<Node>,
+}
+
+impl Pattern {
+    /// Whether `v` matches, filling in the slots of the variables bound on
+    /// the way. Predicates and record types are evaluated in `frame`, the
+    /// one the `match` itself runs in.
+    pub fn matches(&self, v: &Value, slots: &mut [Value], frame: &Option<Rc<Frame>>, ctx: &Context)
+                   -> Result<bool, Error> {
+        match *self {
+            Pattern::Wildcard => Ok(true),
+            Pattern::Bind(i) => {
+                slots[i] = v.clone();
+                Ok(true)
+            }
+            Pattern::Same(i) => Ok(slots[i].is_equal(v)),
+            Pattern::Literal(ref x) => Ok(x.is_equal(v)),
+            Pattern::List(ref items, ref tail) => {
+                let mut cur = v.clone();
+                for item in items {
+                    let (car, cdr) = match cur {
+                        Value::Pair(ref p) => (p.car.clone(), p.cdr.clone()),
+                        _ => return Ok(false),
+                    };
+                    if !item.matches(&car, slots, frame, ctx)? {
+                        return Ok(false);
+                    }
+                    cur = cdr;
+                }
+                match *tail {
+                    Some(ref tail) => tail.matches(&cur, slots, frame, ctx),
+                    None => Ok(matches!(cur, Value::Nil)),
+                }
+            }
+            Pattern::Vector(ref items) => match *v {
+                Value::Vector(ref values) => {
+                    let values = values.borrow().clone();
+                    Ok(values.len() == items.len() && all(items, &values, slots, frame, ctx)?)
+                }
+                _ => Ok(false),
+            },
+            Pattern::Pred(ref pred, ref patterns) => {
+                let pred = eval_node(pred, frame, ctx)?;
+                if !apply(&pred, vec![v.clone()], ctx)?.is_true() {
+                    return Ok(false);
+                }
+                for pattern in patterns {
+                    if !pattern.matches(v, slots, frame, ctx)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Pattern::Record(ref ty, ref fields) => {
+                let ty = match eval_node(ty, frame, ctx)? {
+                    Value::RecordType(ty) => ty,
+                    ref v => return Err(type_error("record type", v)),
+                };
+                if fields.len() > ty.fields.len() {
+                    return Err(Error::Arity(format!("{} has {} fields, got {} patterns", ty.name, ty.fields.len(),
+                                                    fields.len())));
+                }
+                match *v {
+                    Value::Record(ref r) if Rc::ptr_eq(&r.ty, &ty) => all(fields, &r.values(), slots, frame, ctx),
+                    _ => Ok(false),
+                }
+            }
+        }
+    }
+}
+
+/// Whether each value matches the pattern in the same position.
+fn all(patterns: &[Pattern], values: &[Value], slots: &mut [Value], frame: &Option<Rc<Frame>>, ctx: &Context)
+       -> Result<bool, Error> {
+    for (pattern, v) in patterns.iter().zip(values) {
+        if !pattern.matches(v, slots, frame, ctx)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[test]
+fn match_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define (f x) (match x (0 'zero) (\"s\" 'string) (#t 'true) ('sym 'quoted) (_ 'other)))
+                  (list (f 0) (f \"s\") (f #t) (f 'sym) (f 'else))"),
+               s("'(zero string true quoted other)"));
+    assert_eq!(s("(match '(1 (2 3) 4) ((a (b c) d) (list d c b a)))"), s("'(4 3 2 1)"));
+    assert_eq!(s("(match '(1 2 3) ((x . rest) rest))"), s("'(2 3)"));
+    assert_eq!(s("(match '(1 2) ((a) 'one) ((a b c) 'three) ((a b) 'two))"), s("'two"));
+    assert_eq!(s("(match '(1 1 2) ((x x y) (list x y)))"), s("'(1 2)"));
+    assert_eq!(s("(match '(1 2 2) ((x x y) 'same) (_ 'different))"), s("'different"));
+    assert_eq!(s("(match '(add 1 2) (`(add ,a ,b) (+ a b)) (`(neg ,a) (- a)))"), Ok(Value::Int(3)));
+    assert_eq!(s("(match '(point (1 . 2)) (`(point (,x . ,y)) (list y x)))"), s("'(2 1)"));
+    assert_eq!(s("(match #(1 (2)) (#(a (b)) (+ a b)))"), Ok(Value::Int(3)));
+    assert_eq!(s("(match #(1 2) (`#(,a 3) 'no) (`#(,a ,b) (list b a)))"), s("'(2 1)"));
+    assert_eq!(s("(define (kind v) (match v ((? (lambda (x) (eq? x 'a))) 'a) ((? symbol?) 'symbol) (_ 'other)))
+                  (list (kind 'a) (kind 'b) (kind 7))"),
+               s("'(a symbol other)"));
+    assert_eq!(s("(match '(3 4) ((? pair? (a b)) (* a b)))"), Ok(Value::Int(12)));
+    assert_eq!(s("(match '(3 4) ((a b) (when (= a b)) 'equal) ((a b) (when (= (+ a 1) b)) 'next) (_ 'other))"),
+               s("'next"));
+    assert_eq!(s("(define-record-type point (make-point x y) point? (x point-x) (y point-y))
+                  (define (quadrant p) (match p (($ point 0 0) 'origin) (($ point x y) (when (= x 2)) (list 'east y))
+                                                (($ point x) (list 'west x))))
+                  (list (quadrant (make-point 0 0)) (quadrant (make-point 2 3)) (quadrant (make-point -1 3)))"),
+               s("'(origin (east 3) (west -1))"));
+    assert_eq!(s("(define-record-type point (make-point x y) point? (x point-x) (y point-y))
+                  (match '(1 2) (($ point x y) 'point) ((x y) 'list))"),
+               s("'list"));
+    assert_eq!(s("(define (eval-expr e) (match e
+                    (`(+ ,a ,b) (+ (eval-expr a) (eval-expr b)))
+                    (`(* ,a ,b) (* (eval-expr a) (eval-expr b)))
+                    (n n)))
+                  (eval-expr '(+ 1 (* 2 3)))"),
+               Ok(Value::Int(7)));
+    assert_eq!(s("(define (f) (match 5 (x (define y (* x 2)) (+ x y)))) (f)"), Ok(Value::Int(15)));
+
+    assert_eq!(s("(match '(1 2 3) ((a b) 'two))"),
+               Err(Error::User { message: String::from("no matching clause for"), irritants: vec![s("'(1 2 3)").unwrap()] }));
+    assert_eq!(s("(match 1 (x))"), Err(Error::Syntax(String::from("bad match clause (x)"))));
+    assert!(s("(match 1)").is_err());
+    assert_eq!(s("(match 1 (($ car x) x))"), Err(Error::Type(String::from("expected record type, got #<procedure car>"))));
+}
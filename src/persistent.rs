@@ -0,0 +1,603 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::rc::Rc;
+use builtins::{register, type_error, Arity, Builtin, Primitive};
+use env::Env;
+use error::Error;
+use hash_table::hash_equal;
+use value::{count_cells, Value};
+
+/// Bits of a hash, or of an index, consumed by each level of a trie.
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: u64 = WIDTH as u64 - 1;
+
+/// An immutable map keyed by `equal?`: a hash array mapped trie whose
+/// updates copy only the path to the changed entry and share the rest with
+/// the map they were made from.
+#[derive(Clone, Default)]
+pub struct Map {
+    root: Option<Rc<Node>>,
+    len: usize,
+}
+
+enum Node {
+    /// The entries whose hashes have the set bits of `bitmap` as their
+    /// next five bits, in bit order.
+    Branch(u32, Vec<Entry>),
+    /// Keys whose hashes are all the same.
+    Collision(u64, Vec<(Value, Value)>),
+}
+
+#[derive(Clone)]
+enum Entry {
+    Leaf(u64, Value, Value),
+    Node(Rc<Node>),
+}
+
+fn hash_of(v: &Value) -> u64 {
+    let mut state = DefaultHasher::new();
+    hash_equal(v, &mut state);
+    state.finish()
+}
+
+/// The slot of `hash` in a branch at `shift` with the given bitmap, and
+/// whether it is taken.
+fn slot(bitmap: u32, hash: u64, shift: u32) -> (u32, usize) {
+    let bit = 1 << ((hash >> shift) & MASK);
+    (bit, (bitmap & (bit - 1)).count_ones() as usize)
+}
+
+impl Node {
+    /// The smallest node holding two entries with different keys.
+    fn pair(shift: u32, a: (u64, Value, Value), b: (u64, Value, Value)) -> Node {
+        if shift >= 64 {
+            return Node::Collision(a.0, vec![(a.1, a.2), (b.1, b.2)]);
+        }
+        let (ia, ib) = ((a.0 >> shift) & MASK, (b.0 >> shift) & MASK);
+        let bitmap = (1 << ia) | (1 << ib);
+        if ia == ib {
+            Node::Branch(bitmap, vec![Entry::Node(Rc::new(Node::pair(shift + BITS, a, b)))])
+        } else {
+            let (a, b) = (Entry::Leaf(a.0, a.1, a.2), Entry::Leaf(b.0, b.1, b.2));
+            Node::Branch(bitmap, if ia < ib { vec![a, b] } else { vec![b, a] })
+        }
+    }
+
+    fn get(&self, hash: u64, key: &Value, shift: u32) -> Option<&Value> {
+        match *self {
+            Node::Branch(bitmap, ref entries) => {
+                let (bit, i) = slot(bitmap, hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                match entries[i] {
+                    Entry::Leaf(h, ref k, ref v) => if h == hash && k.is_equal(key) { Some(v) } else { None },
+                    Entry::Node(ref node) => node.get(hash, key, shift + BITS),
+                }
+            }
+            Node::Collision(_, ref entries) => entries.iter().find(|e| e.0.is_equal(key)).map(|e| &e.1),
+        }
+    }
+
+    /// The node with `key` set to `value`, and whether `key` is new.
+    fn insert(&self, hash: u64, key: Value, value: Value, shift: u32) -> (Node, bool) {
+        match *self {
+            Node::Branch(bitmap, ref entries) => {
+                let (bit, i) = slot(bitmap, hash, shift);
+                let mut entries = entries.clone();
+                if bitmap & bit == 0 {
+                    entries.insert(i, Entry::Leaf(hash, key, value));
+                    return (Node::Branch(bitmap | bit, entries), true);
+                }
+                let (entry, added) = match entries[i] {
+                    Entry::Leaf(h, ref k, _) if h == hash && k.is_equal(&key) => (Entry::Leaf(hash, key, value), false),
+                    Entry::Leaf(h, ref k, ref v) => {
+                        let node = Node::pair(shift + BITS, (h, k.clone(), v.clone()), (hash, key, value));
+                        (Entry::Node(Rc::new(node)), true)
+                    }
+                    Entry::Node(ref node) => {
+                        let (node, added) = node.insert(hash, key, value, shift + BITS);
+                        (Entry::Node(Rc::new(node)), added)
+                    }
+                };
+                entries[i] = entry;
+                (Node::Branch(bitmap, entries), added)
+            }
+            Node::Collision(h, ref entries) => {
+                let mut entries = entries.clone();
+                match entries.iter().position(|e| e.0.is_equal(&key)) {
+                    Some(i) => {
+                        entries[i].1 = value;
+                        (Node::Collision(h, entries), false)
+                    }
+                    None => {
+                        entries.push((key, value));
+                        (Node::Collision(h, entries), true)
+                    }
+                }
+            }
+        }
+    }
+
+    /// The node without `key`: `None` if it has no such key, and `Some(None)`
+    /// if it would be left empty.
+    fn remove(&self, hash: u64, key: &Value, shift: u32) -> Option<Option<Node>> {
+        match *self {
+            Node::Branch(bitmap, ref entries) => {
+                let (bit, i) = slot(bitmap, hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                let replacement = match entries[i] {
+                    Entry::Leaf(h, ref k, _) if h == hash && k.is_equal(key) => None,
+                    Entry::Leaf(..) => return None,
+                    Entry::Node(ref node) => node.remove(hash, key, shift + BITS)?.map(|n| Entry::Node(Rc::new(n))),
+                };
+                let mut entries = entries.clone();
+                match replacement {
+                    Some(entry) => entries[i] = entry,
+                    None if entries.len() == 1 => return Some(None),
+                    None => {
+                        entries.remove(i);
+                        return Some(Some(Node::Branch(bitmap & !bit, entries)));
+                    }
+                }
+                Some(Some(Node::Branch(bitmap, entries)))
+            }
+            Node::Collision(h, ref entries) => {
+                let i = entries.iter().position(|e| e.0.is_equal(key))?;
+                if entries.len() == 1 {
+                    return Some(None);
+                }
+                let mut entries = entries.clone();
+                entries.remove(i);
+                Some(Some(Node::Collision(h, entries)))
+            }
+        }
+    }
+
+    fn for_each<F: FnMut(&Value, &Value)>(&self, f: &mut F) {
+        match *self {
+            Node::Branch(_, ref entries) => for entry in entries {
+                match *entry {
+                    Entry::Leaf(_, ref k, ref v) => f(k, v),
+                    Entry::Node(ref node) => node.for_each(f),
+                }
+            },
+            Node::Collision(_, ref entries) => for (k, v) in entries {
+                f(k, v)
+            },
+        }
+    }
+}
+
+impl Map {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.root.as_ref()?.get(hash_of(key), key, 0)
+    }
+
+    pub fn insert(&self, key: Value, value: Value) -> Map {
+        let hash = hash_of(&key);
+        let (root, added) = match self.root {
+            Some(ref root) => root.insert(hash, key, value, 0),
+            None => Node::Branch(0, vec![]).insert(hash, key, value, 0),
+        };
+        count_cells(1);
+        Map { root: Some(Rc::new(root)), len: self.len + added as usize }
+    }
+
+    pub fn remove(&self, key: &Value) -> Map {
+        let removed = self.root.as_ref().and_then(|root| root.remove(hash_of(key), key, 0));
+        match removed {
+            None => self.clone(),
+            Some(root) => Map { root: root.map(Rc::new), len: self.len - 1 },
+        }
+    }
+
+    /// The entries, in an order that depends only on the keys.
+    pub fn entries(&self) -> Vec<(Value, Value)> {
+        let mut entries = Vec::with_capacity(self.len);
+        if let Some(ref root) = self.root {
+            root.for_each(&mut |k, v| entries.push((k.clone(), v.clone())));
+        }
+        entries
+    }
+
+    /// Whether both maps are the same version.
+    pub fn is_same(&self, other: &Map) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+
+    /// A hash of the entries, each hashed with `hash`, that does not
+    /// depend on their order.
+    pub fn hash_entries<F: Fn(&Value) -> u64>(&self, hash: F) -> u64 {
+        self.entries().iter().fold(0, |sum, (k, v)| sum.wrapping_add(hash(k) ^ hash(v).rotate_left(1)))
+    }
+}
+
+/// An immutable set compared with `equal?`: a map from its members to
+/// themselves.
+#[derive(Clone, Default)]
+pub struct Set {
+    map: Map,
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn get(&self, item: &Value) -> Option<&Value> {
+        self.map.get(item)
+    }
+
+    pub fn insert(&self, item: Value) -> Set {
+        Set { map: self.map.insert(item.clone(), item) }
+    }
+
+    pub fn remove(&self, item: &Value) -> Set {
+        Set { map: self.map.remove(item) }
+    }
+
+    pub fn items(&self) -> Vec<Value> {
+        self.map.entries().into_iter().map(|e| e.0).collect()
+    }
+
+    pub fn is_same(&self, other: &Set) -> bool {
+        self.map.is_same(&other.map)
+    }
+
+    pub fn is_equal(&self, other: &Set) -> bool {
+        self.is_same(other) || (self.len() == other.len() && self.items().iter().all(|x| other.get(x).is_some()))
+    }
+
+    pub fn hash_items<F: Fn(&Value) -> u64>(&self, hash: F) -> u64 {
+        self.items().iter().fold(0, |sum, x| sum.wrapping_add(hash(x)))
+    }
+}
+
+/// An immutable vector: a trie of 32-wide nodes indexed by the bits of the
+/// position, five at a time, so that `get`, `persistent-assoc` and `conj` copy at most
+/// one node per level.
+#[derive(Clone)]
+pub struct PVector {
+    root: Rc<Chunk>,
+    /// How far to shift an index for the slot in the root.
+    shift: u32,
+    len: usize,
+}
+
+enum Chunk {
+    Branch(Vec<Rc<Chunk>>),
+    Leaf(Vec<Value>),
+}
+
+impl Chunk {
+    /// A path of branches from `shift` down to a leaf holding `v`.
+    fn path(shift: u32, v: Value) -> Chunk {
+        match shift {
+            0 => Chunk::Leaf(vec![v]),
+            _ => Chunk::Branch(vec![Rc::new(Chunk::path(shift - BITS, v))]),
+        }
+    }
+
+    /// The chunk with the element at `i` set to `v`, where `i` may be one
+    /// past the last element to add one.
+    fn set(&self, shift: u32, i: usize, v: Value) -> Chunk {
+        let slot = (i >> shift) & (WIDTH - 1);
+        match *self {
+            Chunk::Leaf(ref items) => {
+                let mut items = items.clone();
+                if slot < items.len() { items[slot] = v } else { items.push(v) }
+                Chunk::Leaf(items)
+            }
+            Chunk::Branch(ref children) => {
+                let mut children = children.clone();
+                if slot < children.len() {
+                    children[slot] = Rc::new(children[slot].set(shift - BITS, i, v));
+                } else {
+                    children.push(Rc::new(Chunk::path(shift - BITS, v)));
+                }
+                Chunk::Branch(children)
+            }
+        }
+    }
+}
+
+impl Default for PVector {
+    fn default() -> PVector {
+        PVector { root: Rc::new(Chunk::Leaf(vec![])), shift: 0, len: 0 }
+    }
+}
+
+impl PVector {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> Option<&Value> {
+        if i >= self.len {
+            return None;
+        }
+        let mut chunk = &*self.root;
+        let mut shift = self.shift;
+        loop {
+            match *chunk {
+                Chunk::Branch(ref children) => {
+                    chunk = &children[(i >> shift) & (WIDTH - 1)];
+                    shift -= BITS;
+                }
+                Chunk::Leaf(ref items) => return Some(&items[i & (WIDTH - 1)]),
+            }
+        }
+    }
+
+    /// The vector with the element at `i` replaced by `v`, or with `v` added
+    /// at the end if `i` is its length.
+    pub fn set(&self, i: usize, v: Value) -> PVector {
+        count_cells(1);
+        if i < self.len {
+            return PVector { root: Rc::new(self.root.set(self.shift, i, v)), ..self.clone() };
+        }
+        if self.len < WIDTH << self.shift {
+            return PVector { root: Rc::new(self.root.set(self.shift, i, v)), shift: self.shift, len: self.len + 1 };
+        }
+        let grown = Chunk::Branch(vec![Rc::clone(&self.root), Rc::new(Chunk::path(self.shift, v))]);
+        PVector { root: Rc::new(grown), shift: self.shift + BITS, len: self.len + 1 }
+    }
+
+    pub fn push(&self, v: Value) -> PVector {
+        self.set(self.len, v)
+    }
+
+    pub fn items(&self) -> Vec<Value> {
+        (0..self.len).map(|i| self.get(i).unwrap().clone()).collect()
+    }
+
+    pub fn is_same(&self, other: &PVector) -> bool {
+        Rc::ptr_eq(&self.root, &other.root)
+    }
+}
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    ("persistent-map", Arity::AtLeast(0), persistent_map),
+    ("persistent-set", Arity::AtLeast(0), persistent_set),
+    ("persistent-vector", Arity::AtLeast(0), persistent_vector),
+    ("persistent-map?", Arity::Exact(1), is_persistent_map),
+    ("persistent-set?", Arity::Exact(1), is_persistent_set),
+    ("persistent-vector?", Arity::Exact(1), is_persistent_vector),
+    ("persistent->list", Arity::Exact(1), persistent_to_list),
+    ("persistent-assoc", Arity::AtLeast(1), persistent_assoc),
+    ("dissoc", Arity::AtLeast(1), dissoc),
+    ("conj", Arity::AtLeast(1), conj),
+    ("disj", Arity::AtLeast(1), disj),
+    ("get", Arity::Range(2, 3), get),
+    ("contains?", Arity::Exact(2), contains),
+    ("count", Arity::Exact(1), count),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+}
+
+fn index(v: &Value) -> Option<usize> {
+    match *v {
+        Value::Int(i) if i >= 0 => Some(i as usize),
+        _ => None,
+    }
+}
+
+fn pairs<'a>(name: &str, vals: &'a [Value]) -> Result<::std::slice::Chunks<'a, Value>, Error> {
+    if !vals.len().is_multiple_of(2) {
+        return Err(Error::Arity(format!("{} expects keys and values in pairs", name)));
+    }
+    Ok(vals.chunks(2))
+}
+
+/// `(persistent-map k1 v1 k2 v2 ...)`.
+fn persistent_map(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Map(pairs("persistent-map", vals)?.fold(Map::default(), |m, kv| m.insert(kv[0].clone(), kv[1].clone()))))
+}
+
+fn persistent_set(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Set(vals.iter().fold(Set::default(), |s, x| s.insert(x.clone()))))
+}
+
+fn persistent_vector(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::PVector(vals.iter().fold(PVector::default(), |v, x| v.push(x.clone()))))
+}
+
+fn is_persistent_map(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Map(_))))
+}
+
+fn is_persistent_set(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Set(_))))
+}
+
+fn is_persistent_vector(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::PVector(_))))
+}
+
+/// The entries of a map as an association list, or the members of a set
+/// or a vector.
+fn persistent_to_list(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Map(ref m) => Ok(Value::list(m.entries().into_iter().map(|(k, v)| Value::cons(k, v)).collect())),
+        Value::Set(ref s) => Ok(Value::list(s.items())),
+        Value::PVector(ref v) => Ok(Value::list(v.items())),
+        ref v => Err(type_error("persistent collection", v)),
+    }
+}
+
+/// `(persistent-assoc map k1 v1 ...)` and `(persistent-assoc pvector i1
+/// v1 ...)` return a new version with the given keys or positions set; a
+/// position may be the length of the vector, to add to its end.
+fn persistent_assoc(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Map(ref m) => Ok(Value::Map(pairs("persistent-assoc", &vals[1..])?
+            .fold(m.clone(), |m, kv| m.insert(kv[0].clone(), kv[1].clone())))),
+        Value::PVector(ref v) => {
+            let mut v = v.clone();
+            for iv in pairs("persistent-assoc", &vals[1..])? {
+                v = match index(&iv[0]) {
+                    Some(i) if i <= v.len() => v.set(i, iv[1].clone()),
+                    _ => return Err(Error::Range(format!("index {} of a persistent vector of length {}", iv[0], v.len()))),
+                };
+            }
+            Ok(Value::PVector(v))
+        }
+        ref v => Err(type_error("persistent map or vector", v)),
+    }
+}
+
+/// `(dissoc map k ...)`: the map without the given keys.
+fn dissoc(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Map(ref m) => Ok(Value::Map(vals[1..].iter().fold(m.clone(), |m, k| m.remove(k)))),
+        ref v => Err(type_error("persistent map", v)),
+    }
+}
+
+/// `(conj coll x ...)` adds members to a set, elements to the end of a
+/// vector, or `(key . value)` pairs to a map.
+fn conj(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Set(ref s) => Ok(Value::Set(vals[1..].iter().fold(s.clone(), |s, x| s.insert(x.clone())))),
+        Value::PVector(ref v) => Ok(Value::PVector(vals[1..].iter().fold(v.clone(), |v, x| v.push(x.clone())))),
+        Value::Map(ref m) => {
+            let mut m = m.clone();
+            for entry in &vals[1..] {
+                m = match *entry {
+                    Value::Pair(ref p) => m.insert(p.car.clone(), p.cdr.clone()),
+                    ref v => return Err(type_error("(key . value) pair", v)),
+                };
+            }
+            Ok(Value::Map(m))
+        }
+        ref v => Err(type_error("persistent collection", v)),
+    }
+}
+
+/// `(disj set x ...)`: the set without the given members.
+fn disj(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Set(ref s) => Ok(Value::Set(vals[1..].iter().fold(s.clone(), |s, x| s.remove(x)))),
+        ref v => Err(type_error("persistent set", v)),
+    }
+}
+
+/// `(get coll key [default])` is the value of a key of a map, the member of
+/// a set `equal?` to `key`, or the element of a vector at a position;
+/// `default`, or `#f`, if there is none.
+fn get(vals: &[Value]) -> Result<Value, Error> {
+    let found = match vals[0] {
+        Value::Map(ref m) => m.get(&vals[1]),
+        Value::Set(ref s) => s.get(&vals[1]),
+        Value::PVector(ref v) => index(&vals[1]).and_then(|i| v.get(i)),
+        ref v => return Err(type_error("persistent collection", v)),
+    };
+    Ok(found.or_else(|| vals.get(2)).cloned().unwrap_or(Value::Bool(false)))
+}
+
+/// Whether a map has a key, a set a member, or a vector a position.
+fn contains(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(match vals[0] {
+        Value::Map(ref m) => m.get(&vals[1]).is_some(),
+        Value::Set(ref s) => s.get(&vals[1]).is_some(),
+        Value::PVector(ref v) => index(&vals[1]).is_some_and(|i| i < v.len()),
+        ref v => return Err(type_error("persistent collection", v)),
+    }))
+}
+
+/// The number of entries of a persistent collection, hash table, vector or
+/// list.
+fn count(vals: &[Value]) -> Result<Value, Error> {
+    let n = match vals[0] {
+        Value::Map(ref m) => m.len(),
+        Value::Set(ref s) => s.len(),
+        Value::PVector(ref v) => v.len(),
+        Value::Hash(ref table) => table.borrow().len(),
+        Value::Vector(ref items) => items.borrow().len(),
+        ref v => v.to_vec().ok_or_else(|| type_error("collection", v))?.len(),
+    };
+    Ok(Value::Int(n as i64))
+}
+
+#[test]
+fn persistent_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("(define m (persistent-map 'a 1 'b 2)) (define n (persistent-assoc m 'a 10 'c 3))
+                  (list (get m 'a) (get n 'a) (get n 'c) (get m 'c) (get m 'c 'none) (count m) (count n))"),
+               s("'(1 10 3 #f none 2 3)"));
+    assert_eq!(s("(define m (persistent-map '(1 \"x\") 'found)) (list (get m (list 1 \"x\")) (contains? m '(1 \"x\")))"),
+               s("'(found #t)"));
+    assert_eq!(s("(define m (persistent-map 'a 1 'b 2)) (define n (dissoc m 'a 'z))
+                  (list (contains? m 'a) (contains? n 'a) (count n) (persistent->list n))"),
+               s("'(#t #f 1 ((b . 2)))"));
+    assert_eq!(s("(equal? (persistent-map 'a 1 'b 2) (dissoc (persistent-assoc (persistent-map 'b 2) 'c 3 'a 1) 'c))"),
+               Ok(Value::Bool(true)));
+    assert_eq!(s("(list (equal? (persistent-map 'a 1) (persistent-map 'a 2)) (eqv? (persistent-map 'a 1) (persistent-map 'a 1)))"),
+               s("'(#f #f)"));
+    assert_eq!(s("(conj (persistent-map) '(a . 1))"), s("(persistent-map 'a 1)"));
+
+    assert_eq!(s("(define s (persistent-set 1 2 2 3)) (define t (disj (conj s 4) 1))
+                  (list (count s) (count t) (contains? s 1) (contains? t 1) (get t 4) (get t 5))"),
+               s("'(3 3 #t #f 4 #f)"));
+    assert_eq!(s("(equal? (persistent-set 1 '(2) \"3\") (persistent-set \"3\" (list 2) 1))"), Ok(Value::Bool(true)));
+
+    assert_eq!(s("(define v (persistent-vector 1 2 3)) (define w (persistent-assoc (conj v 4) 0 'x 4 5))
+                  (list (persistent->list v) (persistent->list w) (get w 9) (contains? w 4) (contains? w 5))"),
+               s("'((1 2 3) (x 2 3 4 5) #f #t #f)"));
+    assert_eq!(s("(persistent-assoc (persistent-vector) 1 'x)"),
+               Err(Error::Range(String::from("index 1 of a persistent vector of length 0"))));
+    assert_eq!(s("(persistent-vector 1 (persistent-set 2) (persistent-map 'k 'v))").map(|v| v.to_string()),
+               Ok(String::from("#<pvector 1 #<set 2> #<map (k . v)>>")));
+
+    // Enough entries for several levels of trie, keeping every old version.
+    assert_eq!(s("(define (upto n f) (for-each (lambda (hi) (for-each (lambda (lo) (f (+ (* hi 40) lo))) (range 0 40)))
+                                              (range 0 (/ n 40))))
+                  (define v (persistent-vector)) (define m (persistent-map))
+                  (upto 2000 (lambda (i) (set! v (conj v i)) (set! m (persistent-assoc m i (* i i)))))
+                  (define odd m)
+                  (upto 1000 (lambda (i) (set! odd (dissoc odd (* 2 i)))))
+                  (list (count v) (get v 0) (get v 1057) (get v 1999) (count m) (get m 1999)
+                        (count odd) (get odd 1000) (get odd 1001) (equal? v (apply persistent-vector (persistent->list v))))"),
+               s("'(2000 0 1057 1999 2000 3996001 1000 #f 1002001 #t)"));
+
+    assert_eq!(s("(define h (make-hash-table)) (hash-set! h (persistent-set 1 2) 'yes) (hash-ref h (persistent-set 2 1))"),
+               s("'yes"));
+    assert_eq!(s("(assoc (persistent-map 'a 1) (list (cons (persistent-map 'b 2) 'no) (cons (persistent-map 'a 1) 'yes)))"),
+               s("(cons (persistent-map 'a 1) 'yes)"));
+    assert_eq!(s("(persistent-assoc 2 '((1 . a)))"),
+               Err(Error::Type(String::from("expected persistent map or vector, got 2"))));
+    assert_eq!(s("(list (count '(1 2)) (count #(1)) (count (hash 1 2 3 4)))"), s("'(2 1 2)"));
+    assert_eq!(s("(dissoc (persistent-set 1) 1)"), Err(Error::Type(String::from("expected persistent map, got #<set 1>"))));
+    assert!(s("(persistent-map 'a)").is_err());
+}
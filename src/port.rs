@@ -0,0 +1,385 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Write};
+use std::rc::Rc;
+use builtins::{register, string_arg, type_error, Arity, Builtin};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::apply;
+use expr::Expr;
+use parser::read_datum;
+use sandbox::{Fs, Group, Profile};
+use value::Value;
+
+/// Where a script's input comes from or its output goes to. The host picks
+/// the ports: the CLI uses the standard streams, while an embedder can
+/// capture output with `Port::string_output`.
+pub struct Port {
+    pub name: String,
+    state: RefCell<State>,
+}
+
+enum State {
+    /// `pending` holds what has been read from `reader` but not consumed.
+    Input { reader: Box<dyn BufRead>, pending: String },
+    Output(Box<dyn Write>),
+    StringOutput(String),
+    Closed,
+}
+
+impl Port {
+    pub fn input<R: BufRead + 'static>(name: &str, reader: R) -> Rc<Port> {
+        Port::new(name, State::Input { reader: Box::new(reader), pending: String::new() })
+    }
+
+    pub fn output<W: Write + 'static>(name: &str, writer: W) -> Rc<Port> {
+        Port::new(name, State::Output(Box::new(writer)))
+    }
+
+    /// An input port reading `text`.
+    pub fn from_string(text: &str) -> Rc<Port> {
+        Port::input("string", Cursor::new(text.as_bytes().to_vec()))
+    }
+
+    /// An output port collecting what is written to it; see `contents`.
+    pub fn string_output() -> Rc<Port> {
+        Port::new("string", State::StringOutput(String::new()))
+    }
+
+    pub fn stdin() -> Rc<Port> {
+        Port::input("stdin", io::BufReader::new(io::stdin()))
+    }
+
+    pub fn stdout() -> Rc<Port> {
+        Port::output("stdout", io::stdout())
+    }
+
+    fn new(name: &str, state: State) -> Rc<Port> {
+        Rc::new(Port { name: String::from(name), state: RefCell::new(state) })
+    }
+
+    /// Everything written so far to a port made by `string_output`.
+    pub fn contents(&self) -> Option<String> {
+        match *self.state.borrow() {
+            State::StringOutput(ref s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_input(&self) -> bool {
+        matches!(*self.state.borrow(), State::Input { .. })
+    }
+
+    pub fn is_output(&self) -> bool {
+        matches!(*self.state.borrow(), State::Output(_) | State::StringOutput(_))
+    }
+
+    /// What the port prints as: `input-port`, `output-port`, or just `port`
+    /// once it is closed.
+    pub fn kind(&self) -> &'static str {
+        match *self.state.borrow() {
+            State::Input { .. } => "input-port",
+            State::Output(_) | State::StringOutput(_) => "output-port",
+            State::Closed => "port",
+        }
+    }
+
+    pub fn close(&self) {
+        *self.state.borrow_mut() = State::Closed;
+    }
+
+    fn io_error(&self, e: &io::Error) -> Error {
+        Error::Io(format!("{}: {}", self.name, e))
+    }
+
+    fn closed(&self, direction: &str) -> Error {
+        Error::Io(format!("{}: not an open {} port", self.name, direction))
+    }
+
+    pub fn write_str(&self, s: &str) -> Result<(), Error> {
+        match *self.state.borrow_mut() {
+            State::Output(ref mut w) => w.write_all(s.as_bytes()).and_then(|_| w.flush())
+                .map_err(|e| self.io_error(&e)),
+            State::StringOutput(ref mut buf) => {
+                buf.push_str(s);
+                Ok(())
+            }
+            _ => Err(self.closed("output")),
+        }
+    }
+
+    /// Runs `f` on the unconsumed input, which is topped up a line at a time
+    /// for as long as `f` asks for more by returning `None`. At the end of
+    /// the input `f` is called one last time with `eof` set.
+    fn with_input<T, F>(&self, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(&mut String, bool) -> Option<T>,
+    {
+        let mut state = self.state.borrow_mut();
+        let (reader, pending) = match *state {
+            State::Input { ref mut reader, ref mut pending } => (reader, pending),
+            _ => return Err(self.closed("input")),
+        };
+        loop {
+            if let Some(result) = f(pending, false) {
+                return Ok(result);
+            }
+            let read = reader.read_line(pending).map_err(|e| self.io_error(&e))?;
+            if read == 0 {
+                return Ok(f(pending, true).expect("input callback must finish at eof"));
+            }
+        }
+    }
+
+    /// The next line without its line terminator, or `None` at the end of
+    /// the input.
+    pub fn read_line(&self) -> Result<Option<String>, Error> {
+        self.with_input(|pending, eof| match pending.find('\n') {
+            Some(end) => {
+                let line = pending[..end].trim_end_matches('\r').to_string();
+                pending.drain(..=end);
+                Some(Some(line))
+            }
+            None if eof && pending.is_empty() => Some(None),
+            None if eof => Some(Some(pending.split_off(0))),
+            None => None,
+        })
+    }
+
+    pub fn read_char(&self) -> Result<Option<char>, Error> {
+        self.with_input(|pending, eof| match pending.chars().next() {
+            Some(c) => {
+                pending.drain(..c.len_utf8());
+                Some(Some(c))
+            }
+            None if eof => Some(None),
+            None => None,
+        })
+    }
+
+    pub fn peek_char(&self) -> Result<Option<char>, Error> {
+        self.with_input(|pending, eof| match pending.chars().next() {
+            Some(c) => Some(Some(c)),
+            None if eof => Some(None),
+            None => None,
+        })
+    }
+
+    /// The next datum, or `None` at the end of the input. A datum that does
+    /// not parse keeps reading until the end of the input, as the rest of it
+    /// may still be coming.
+    pub fn read_datum(&self) -> Result<Option<Expr>, Error> {
+        self.with_input(|pending, eof| match read_datum(pending) {
+            Ok(Some((datum, used))) => {
+                pending.drain(..used);
+                Some(Ok(Some(datum)))
+            }
+            Ok(None) if eof => {
+                pending.clear();
+                Some(Ok(None))
+            }
+            Err(e) if eof => Some(Err(e)),
+            _ => None,
+        })?
+    }
+}
+
+/// The current input and output ports of an interpreter.
+pub struct Console {
+    pub input: RefCell<Rc<Port>>,
+    pub output: RefCell<Rc<Port>>,
+}
+
+impl Console {
+    pub fn new(input: Rc<Port>, output: Rc<Port>) -> Rc<Console> {
+        Rc::new(Console { input: RefCell::new(input), output: RefCell::new(output) })
+    }
+
+    pub fn input(&self) -> Rc<Port> {
+        Rc::clone(&self.input.borrow())
+    }
+
+    pub fn output(&self) -> Rc<Port> {
+        Rc::clone(&self.output.borrow())
+    }
+}
+
+type PortFn = fn(&Console, &[Value]) -> Result<Value, Error>;
+
+/// Port procedures that do not touch the file system. Where the port is
+/// optional they default to the console's.
+const PORTS: &[(&str, Arity, PortFn)] = &[
+    ("port?", Arity::Exact(1), is_port),
+    ("input-port?", Arity::Exact(1), is_input_port),
+    ("output-port?", Arity::Exact(1), is_output_port),
+    ("open-input-string", Arity::Exact(1), open_input_string),
+    ("open-output-string", Arity::Exact(0), open_output_string),
+    ("get-output-string", Arity::Exact(1), get_output_string),
+    ("read-char", Arity::Range(0, 1), read_char),
+    ("peek-char", Arity::Range(0, 1), peek_char),
+    ("write-string", Arity::Range(1, 2), write_string),
+    ("close-port", Arity::Exact(1), close_port),
+    ("current-input-port", Arity::Exact(0), current_input_port),
+    ("current-output-port", Arity::Exact(0), current_output_port),
+];
+
+/// Binds the port procedures, with the file ones only as far as `profile`
+/// allows.
+pub fn install(env: &mut Env, profile: &Profile, console: &Rc<Console>) {
+    for &(name, arity, func) in PORTS {
+        let console = Rc::clone(console);
+        register(env, Builtin::new(name, move |args| func(&console, args)).with_arity(arity));
+    }
+    let console = Rc::clone(console);
+    register(env, Builtin::with_context("with-output-to-string", move |ctx, args| {
+        with_output_to_string(&console, ctx, args)
+    }).with_arity(Arity::Exact(1)));
+
+    let fs = Rc::new(profile.fs());
+    if profile.allows(Group::FsRead) {
+        let fs = Rc::clone(&fs);
+        register(env, Builtin::new("open-input-file", move |args| open_input_file(&fs, args))
+            .with_arity(Arity::Exact(1)));
+    }
+    if profile.allows(Group::FsWrite) {
+        let out = Rc::clone(&fs);
+        register(env, Builtin::new("open-output-file", move |args| open_output_file(&out, args))
+            .with_arity(Arity::Exact(1)));
+        register(env, Builtin::with_context("call-with-output-file", move |ctx, args| {
+            call_with_output_file(&fs, ctx, args)
+        }).with_arity(Arity::Exact(2)));
+    }
+}
+
+pub fn port_arg(v: &Value) -> Result<Rc<Port>, Error> {
+    match *v {
+        Value::Port(ref port) => Ok(Rc::clone(port)),
+        ref v => Err(type_error("port", v)),
+    }
+}
+
+/// The port passed as `args[i]`, or `default` if there are fewer arguments.
+pub fn optional_port<F>(args: &[Value], i: usize, default: F) -> Result<Rc<Port>, Error>
+where
+    F: FnOnce() -> Rc<Port>,
+{
+    args.get(i).map_or_else(|| Ok(default()), port_arg)
+}
+
+fn char_value(c: Option<char>) -> Value {
+    c.map_or(Value::Eof, |c| Value::Str(c.to_string().into()))
+}
+
+fn is_port(_: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(args[0], Value::Port(_))))
+}
+
+fn is_input_port(_: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(args[0], Value::Port(ref p) if p.is_input())))
+}
+
+fn is_output_port(_: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(args[0], Value::Port(ref p) if p.is_output())))
+}
+
+fn open_input_string(_: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Port(Port::from_string(string_arg(&args[0])?)))
+}
+
+fn open_output_string(_: &Console, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Port(Port::string_output()))
+}
+
+fn get_output_string(_: &Console, args: &[Value]) -> Result<Value, Error> {
+    match port_arg(&args[0])?.contents() {
+        Some(s) => Ok(Value::Str(s.into())),
+        None => Err(type_error("string output port", &args[0])),
+    }
+}
+
+/// Characters are returned as one-character strings.
+fn read_char(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(char_value(optional_port(args, 0, || console.input())?.read_char()?))
+}
+
+fn peek_char(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(char_value(optional_port(args, 0, || console.input())?.peek_char()?))
+}
+
+fn write_string(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    optional_port(args, 1, || console.output())?.write_str(string_arg(&args[0])?)?;
+    Ok(Value::Unit)
+}
+
+fn close_port(_: &Console, args: &[Value]) -> Result<Value, Error> {
+    port_arg(&args[0])?.close();
+    Ok(Value::Unit)
+}
+
+fn current_input_port(console: &Console, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Port(console.input()))
+}
+
+fn current_output_port(console: &Console, _: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Port(console.output()))
+}
+
+/// `(with-output-to-string thunk)` calls `thunk` with the current output
+/// going to a fresh string port and returns what was written to it.
+fn with_output_to_string(console: &Console, ctx: &Context, args: &[Value]) -> Result<Value, Error> {
+    let port = Port::string_output();
+    let previous = console.output.replace(Rc::clone(&port));
+    let result = apply(&args[0], vec![], ctx);
+    *console.output.borrow_mut() = previous;
+    result?;
+    Ok(Value::Str(port.contents().unwrap_or_default().into()))
+}
+
+fn open_input_file(fs: &Fs, args: &[Value]) -> Result<Value, Error> {
+    let name = string_arg(&args[0])?;
+    let file = File::open(fs.resolve(name)?).map_err(|e| Error::Io(format!("{}: {}", name, e)))?;
+    Ok(Value::Port(Port::input(name, BufReader::new(file))))
+}
+
+fn open_output_file(fs: &Fs, args: &[Value]) -> Result<Value, Error> {
+    let name = string_arg(&args[0])?;
+    let file = File::create(fs.resolve(name)?).map_err(|e| Error::Io(format!("{}: {}", name, e)))?;
+    Ok(Value::Port(Port::output(name, file)))
+}
+
+/// `(call-with-output-file name proc)` calls `proc` with a port writing to
+/// the file, and closes it once `proc` returns.
+fn call_with_output_file(fs: &Fs, ctx: &Context, args: &[Value]) -> Result<Value, Error> {
+    let port = open_output_file(fs, &args[..1])?;
+    let result = apply(&args[1], vec![port.clone()], ctx);
+    if let Value::Port(ref port) = port {
+        port.close();
+    }
+    result
+}
+
+#[test]
+fn port_test() {
+    use symbol::Symbol;
+
+    let port = Port::from_string("first\r\n(a\n b) 2\nlast");
+    assert_eq!(port.read_line(), Ok(Some(String::from("first"))));
+    assert_eq!(port.read_datum(), Ok(Some(Expr::List(vec![Expr::Symbol(Symbol::intern("a")),
+                                                          Expr::Symbol(Symbol::intern("b"))]))));
+    assert_eq!(port.read_datum(), Ok(Some(Expr::Int(2))));
+    assert_eq!(port.read_line(), Ok(Some(String::new())));
+    assert_eq!(port.read_line(), Ok(Some(String::from("last"))));
+    assert_eq!(port.read_line(), Ok(None));
+    assert_eq!(port.read_datum(), Ok(None));
+    assert!(port.write_str("x").is_err());
+
+    assert!(Port::from_string("(a b").read_datum().is_err());
+
+    let out = Port::string_output();
+    out.write_str("a").unwrap();
+    out.write_str("b").unwrap();
+    assert_eq!(out.contents(), Some(String::from("ab")));
+    out.close();
+    assert!(out.write_str("c").is_err());
+}
@@ -0,0 +1,144 @@
+; The standard prelude, evaluated in every new interpreter unless it is
+; created with `Interpreter::bare`. Everything here is an ordinary
+; procedure.
+
+(define (not x) (if x #f #t))
+
+(define (add1 n) (+ n 1))
+(define (sub1 n) (- n 1))
+(define (zero? n) (= n 0))
+
+(define (caar xs) (car (car xs)))
+(define (cadr xs) (car (cdr xs)))
+(define (cdar xs) (cdr (car xs)))
+(define (cddr xs) (cdr (cdr xs)))
+(define (caddr xs) (car (cddr xs)))
+
+(define (list? x)
+  (if (null? x) #t (if (pair? x) (list? (cdr x)) #f)))
+
+(define (length xs) (apply + 0 (map (lambda (x) 1) xs)))
+
+(define (fold-left f acc xs)
+  (if (null? xs) acc (fold-left f (f acc (car xs)) (cdr xs))))
+
+(define (fold-right f acc xs)
+  (if (null? xs) acc (f (car xs) (fold-right f acc (cdr xs)))))
+
+(define (reduce f default xs)
+  (if (null? xs) default (fold-left f (car xs) (cdr xs))))
+
+(define (reverse xs) (fold-left (lambda (acc x) (cons x acc)) '() xs))
+
+; `append` keeps its historical two-argument behaviour; this joins lists.
+(define (concat . lists)
+  (fold-right (lambda (xs acc) (fold-right cons acc xs)) '() lists))
+
+(define (list-tail xs k) (if (zero? k) xs (list-tail (cdr xs) (sub1 k))))
+(define (list-ref xs k) (car (list-tail xs k)))
+(define (last xs) (if (null? (cdr xs)) (car xs) (last (cdr xs))))
+
+(define (filter keep? xs)
+  (fold-right (lambda (x acc) (if (keep? x) (cons x acc) acc)) '() xs))
+
+(define (remove drop? xs) (filter (lambda (x) (not (drop? x))) xs))
+
+(define (for-each f xs)
+  (if (null? xs) unit (begin (f (car xs)) (for-each f (cdr xs)))))
+
+(define (any pred xs)
+  (if (null? xs) #f (if (pred (car xs)) (pred (car xs)) (any pred (cdr xs)))))
+
+(define (every pred xs)
+  (if (null? xs) #t (if (pred (car xs)) (every pred (cdr xs)) #f)))
+
+(define (member x xs)
+  (if (null? xs) #f (if (equal? x (car xs)) xs (member x (cdr xs)))))
+
+; The first pair in `alist` whose car is `equal?` to `key`, or the same
+; under `compare` if one is given.
+(define (assoc key alist . compare)
+  (define same? (if (null? compare) equal? (car compare)))
+  (define (find alist)
+    (if (null? alist) #f (if (same? key (caar alist)) (car alist) (find (cdr alist)))))
+  (find alist))
+
+; (range 0 3) is (0 1 2); `from` must not be above `to`.
+(define (range from to)
+  (if (= from to) '() (cons from (range (add1 from) to))))
+
+; (downto 3 f) is ((f 3) (f 2) (f 1)); `from` must not be negative.
+(define (downto from f)
+  (if (= from 0) '() (cons (f from) (downto (sub1 from) f))))
+
+; Streams are pairs whose cdr is a promise, built with `stream-cons`.
+(define stream-null '())
+(define (stream-null? s) (null? s))
+(define (stream-pair? s) (if (pair? s) (promise? (cdr s)) #f))
+(define (stream-car s) (car s))
+(define (stream-cdr s) (force (cdr s)))
+
+(define (list->stream xs)
+  (if (null? xs) stream-null (stream-cons (car xs) (list->stream (cdr xs)))))
+
+; (stream->list s) forces the whole of a finite stream; (stream->list s n)
+; only its first n elements.
+(define (stream->list s . n)
+  (if (stream-null? s) '()
+      (if (if (pair? n) (zero? (car n)) #f) '()
+          (cons (stream-car s) (apply stream->list (stream-cdr s) (map sub1 n))))))
+
+(define (stream-take n s)
+  (if (if (zero? n) #t (stream-null? s)) stream-null
+      (stream-cons (stream-car s) (stream-take (sub1 n) (stream-cdr s)))))
+
+(define (stream-ref s k) (if (zero? k) (stream-car s) (stream-ref (stream-cdr s) (sub1 k))))
+
+(define (stream-map f s)
+  (if (stream-null? s) stream-null
+      (stream-cons (f (stream-car s)) (stream-map f (stream-cdr s)))))
+
+(define (stream-filter keep? s)
+  (if (stream-null? s) stream-null
+      (if (keep? (stream-car s))
+          (stream-cons (stream-car s) (stream-filter keep? (stream-cdr s)))
+          (stream-filter keep? (stream-cdr s)))))
+
+; (stream-from 0) is the stream 0, 1, 2, ...
+(define (stream-from n) (stream-cons n (stream-from (add1 n))))
+
+; Generators are procedures of no arguments returning the eof object once
+; they are exhausted: those made by `make-generator`, but also closures such
+; as (lambda () (read-line port)).
+(define (list->generator xs)
+  (lambda ()
+    (if (null? xs) (eof-object)
+        ((lambda (x) (set! xs (cdr xs)) x) (car xs)))))
+
+(define (generator . xs) (list->generator xs))
+
+; (generator->list g) drains a finite generator; (generator->list g n) only
+; takes up to n values from it.
+(define (generator->list g . n)
+  (if (if (pair? n) (zero? (car n)) #f) '()
+      ((lambda (x) (if (eof-object? x) '() (cons x (apply generator->list g (map sub1 n))))) (g))))
+
+(define (generator-for-each f g)
+  (define x (g))
+  (if (eof-object? x) unit (begin (f x) (generator-for-each f g))))
+
+(define (generator-fold f acc g)
+  (define x (g))
+  (if (eof-object? x) acc (generator-fold f (f x acc) g)))
+
+(define (gmap f g)
+  (lambda () ((lambda (x) (if (eof-object? x) x (f x))) (g))))
+
+(define (gfilter keep? g)
+  (define (next)
+    (define x (g))
+    (if (eof-object? x) x (if (keep? x) x (next))))
+  next)
+
+(define (gtake g n)
+  (lambda () (if (zero? n) (eof-object) (begin (set! n (sub1 n)) (g)))))
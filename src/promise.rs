@@ -0,0 +1,118 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use builtins::{register, type_error, Arity, Builtin, Control, Primitive};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::apply;
+use value::Value;
+
+/// The result of `delay`, `delay-force` or `make-promise`: a value computed
+/// at most once, when it is first forced.
+///
+/// Promises made by `delay-force` hand their state over to the promise
+/// their thunk returns, as in R7RS, so forcing a long chain of them takes
+/// constant stack space.
+pub struct Promise {
+    state: RefCell<Rc<RefCell<State>>>,
+}
+
+#[derive(Clone)]
+enum State {
+    Done(Value),
+    /// A thunk whose value is the value of the promise.
+    Delayed(Value),
+    /// A thunk returning another promise, whose value is that of this one.
+    DelayForce(Value),
+}
+
+impl Promise {
+    fn with_state(state: State) -> Value {
+        Value::Promise(Rc::new(Promise { state: RefCell::new(Rc::new(RefCell::new(state))) }))
+    }
+
+    pub fn delay(thunk: Value) -> Value {
+        Promise::with_state(State::Delayed(thunk))
+    }
+
+    pub fn delay_force(thunk: Value) -> Value {
+        Promise::with_state(State::DelayForce(thunk))
+    }
+
+    fn cell(&self) -> Rc<RefCell<State>> {
+        Rc::clone(&self.state.borrow())
+    }
+
+    pub fn force(&self, ctx: &Context) -> Result<Value, Error> {
+        loop {
+            let cell = self.cell();
+            let state = cell.borrow().clone();
+            match state {
+                State::Done(v) => return Ok(v),
+                State::Delayed(thunk) => {
+                    let v = apply(&thunk, vec![], ctx)?;
+                    // Forcing the promise from its own thunk may have
+                    // finished it already; the first value wins.
+                    if let State::Done(ref done) = *cell.borrow() {
+                        return Ok(done.clone());
+                    }
+                    *cell.borrow_mut() = State::Done(v.clone());
+                    return Ok(v);
+                }
+                State::DelayForce(thunk) => {
+                    let next = match apply(&thunk, vec![], ctx)? {
+                        Value::Promise(p) => p,
+                        ref v => return Err(type_error("promise from delay-force", v)),
+                    };
+                    if let State::Done(_) = *cell.borrow() {
+                        continue;
+                    }
+                    let next_cell = next.cell();
+                    let next_state = next_cell.borrow().clone();
+                    *cell.borrow_mut() = next_state;
+                    *next.state.borrow_mut() = cell;
+                }
+            }
+        }
+    }
+}
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    ("make-promise", Arity::Exact(1), make_promise),
+    ("promise?", Arity::Exact(1), is_promise),
+];
+
+const CONTROL: &[(&str, Arity, Control)] = &[
+    ("force", Arity::Exact(1), force),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+    for &(name, arity, func) in CONTROL {
+        register(env, Builtin::with_context(name, func).with_arity(arity));
+    }
+}
+
+/// `(make-promise v)` is an already forced promise of `v`, or `v` itself
+/// if it is a promise.
+fn make_promise(vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        ref p @ Value::Promise(_) => Ok(p.clone()),
+        ref v => Ok(Promise::with_state(State::Done(v.clone()))),
+    }
+}
+
+fn is_promise(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Promise(_))))
+}
+
+/// `(force p)` is the value of the promise `p`; anything else is returned
+/// as it is.
+fn force(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    match vals[0] {
+        Value::Promise(ref p) => p.force(ctx),
+        ref v => Ok(v.clone()),
+    }
+}
@@ -0,0 +1,301 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use builtins::{register, type_error, Arity, Builtin, Primitive};
+use convert::FromLisp;
+use env::Env;
+use error::Error;
+use symbol::Symbol;
+use value::{count_cells, Value};
+
+/// A record type made by `define-record-type`, or by the host: its name,
+/// without any angle brackets, and the names of its fields.
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<Symbol>,
+}
+
+/// An instance of a record type, with a value for each of its fields.
+pub struct Record {
+    pub ty: Rc<RecordType>,
+    values: RefCell<Vec<Value>>,
+}
+
+impl RecordType {
+    pub fn new(name: &str, fields: &[&str]) -> Rc<RecordType> {
+        let name = name.trim_start_matches('<').trim_end_matches('>');
+        Rc::new(RecordType { name: String::from(name), fields: fields.iter().map(|f| Symbol::intern(f)).collect() })
+    }
+
+    pub fn field_index(&self, field: Symbol) -> Option<usize> {
+        self.fields.iter().position(|&f| f == field)
+    }
+
+    /// A record of this type with the given field values, in order.
+    pub fn make(self: &Rc<Self>, values: Vec<Value>) -> Result<Value, Error> {
+        if values.len() != self.fields.len() {
+            return Err(Error::Arity(format!("{} has {} fields, got {} values", self.name, self.fields.len(),
+                                            values.len())));
+        }
+        count_cells(values.len());
+        Ok(Value::Record(Rc::new(Record { ty: Rc::clone(self), values: RefCell::new(values) })))
+    }
+
+    fn field_arg(&self, v: &Value) -> Result<usize, Error> {
+        match *v {
+            Value::Symbol(field) => self.field_index(field)
+                .ok_or_else(|| Error::Type(format!("{} has no field {}", self.name, field))),
+            ref v => Err(type_error("field name", v)),
+        }
+    }
+
+    /// The record of this type `v` is, if it is one.
+    fn instance<'a>(self: &Rc<Self>, v: &'a Value) -> Result<&'a Record, Error> {
+        match *v {
+            Value::Record(ref r) if Rc::ptr_eq(&r.ty, self) => Ok(r),
+            ref v => Err(type_error(&self.name, v)),
+        }
+    }
+
+    /// A constructor taking the given fields, in order; fields it does not
+    /// take start out as `unit`.
+    pub fn constructor(self: &Rc<Self>, name: &str, fields: &[Symbol]) -> Result<Builtin, Error> {
+        let slots = fields.iter()
+            .map(|&f| self.field_arg(&Value::Symbol(f)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ty = Rc::clone(self);
+        Ok(Builtin::new(name, move |args| {
+            let mut values = vec![Value::Unit; ty.fields.len()];
+            for (&slot, arg) in slots.iter().zip(args) {
+                values[slot] = arg.clone();
+            }
+            ty.make(values)
+        }).with_arity(Arity::Exact(fields.len())))
+    }
+
+    pub fn predicate(self: &Rc<Self>, name: &str) -> Builtin {
+        let ty = Rc::clone(self);
+        Builtin::new(name, move |args| Ok(Value::Bool(ty.instance(&args[0]).is_ok())))
+            .with_arity(Arity::Exact(1))
+    }
+
+    pub fn accessor(self: &Rc<Self>, name: &str, field: Symbol) -> Result<Builtin, Error> {
+        let slot = self.field_arg(&Value::Symbol(field))?;
+        let ty = Rc::clone(self);
+        Ok(Builtin::new(name, move |args| Ok(ty.instance(&args[0])?.values.borrow()[slot].clone()))
+            .with_arity(Arity::Exact(1)))
+    }
+
+    pub fn modifier(self: &Rc<Self>, name: &str, field: Symbol) -> Result<Builtin, Error> {
+        let slot = self.field_arg(&Value::Symbol(field))?;
+        let ty = Rc::clone(self);
+        Ok(Builtin::new(name, move |args| {
+            ty.instance(&args[0])?.values.borrow_mut()[slot] = args[1].clone();
+            Ok(Value::Unit)
+        }).with_arity(Arity::Exact(2)))
+    }
+
+    /// The procedures `define-record-type` would define for this type, named
+    /// the usual way: `make-point`, `point?`, `point-x` and `set-point-x!`.
+    pub fn procedures(self: &Rc<Self>) -> Vec<Builtin> {
+        let mut procs = vec![
+            self.constructor(&format!("make-{}", self.name), &self.fields).unwrap(),
+            self.predicate(&format!("{}?", self.name)),
+        ];
+        for &field in &self.fields {
+            procs.push(self.accessor(&format!("{}-{}", self.name, field), field).unwrap());
+            procs.push(self.modifier(&format!("set-{}-{}!", self.name, field), field).unwrap());
+        }
+        procs
+    }
+}
+
+impl Record {
+    pub fn get(&self, field: &str) -> Option<Value> {
+        let slot = self.ty.field_index(Symbol::intern(field))?;
+        Some(self.values.borrow()[slot].clone())
+    }
+
+    /// Sets a field, returning whether the record has it.
+    pub fn set(&self, field: &str, value: Value) -> bool {
+        match self.ty.field_index(Symbol::intern(field)) {
+            Some(slot) => {
+                self.values.borrow_mut()[slot] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The values of the fields, in the order of the type's fields.
+    pub fn values(&self) -> Vec<Value> {
+        self.values.borrow().clone()
+    }
+}
+
+impl FromLisp for Rc<Record> {
+    fn from_lisp(value: &Value) -> Result<Rc<Record>, Error> {
+        match *value {
+            Value::Record(ref r) => Ok(Rc::clone(r)),
+            ref v => Err(type_error("record", v)),
+        }
+    }
+}
+
+impl FromLisp for Rc<RecordType> {
+    fn from_lisp(value: &Value) -> Result<Rc<RecordType>, Error> {
+        match *value {
+            Value::RecordType(ref ty) => Ok(Rc::clone(ty)),
+            ref v => Err(type_error("record type", v)),
+        }
+    }
+}
+
+/// The procedures `define-record-type` expands into calls of.
+pub const MAKE_TYPE: (&str, Primitive) = ("make-record-type", make_record_type);
+pub const CONSTRUCTOR: (&str, Primitive) = ("record-constructor", record_constructor);
+pub const PREDICATE: (&str, Primitive) = ("record-predicate", record_predicate);
+pub const ACCESSOR: (&str, Primitive) = ("record-accessor", record_accessor);
+pub const MODIFIER: (&str, Primitive) = ("record-modifier", record_modifier);
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    (MAKE_TYPE.0, Arity::Exact(2), make_record_type),
+    (CONSTRUCTOR.0, Arity::Range(1, 2), record_constructor),
+    (PREDICATE.0, Arity::Exact(1), record_predicate),
+    (ACCESSOR.0, Arity::Exact(2), record_accessor),
+    (MODIFIER.0, Arity::Exact(2), record_modifier),
+    ("record?", Arity::Exact(1), is_record),
+    ("record-type-descriptor", Arity::Exact(1), record_type_descriptor),
+    ("record-type-name", Arity::Exact(1), record_type_name),
+    ("record-type-fields", Arity::Exact(1), record_type_fields),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+}
+
+fn symbol_arg(v: &Value) -> Result<Symbol, Error> {
+    match *v {
+        Value::Symbol(s) => Ok(s),
+        ref v => Err(type_error("symbol", v)),
+    }
+}
+
+fn symbols_arg(v: &Value) -> Result<Vec<Symbol>, Error> {
+    v.to_vec().ok_or_else(|| type_error("list of field names", v))?.iter().map(symbol_arg).collect()
+}
+
+fn proc(builtin: Builtin) -> Value {
+    Value::Builtin(Rc::new(builtin))
+}
+
+/// `(make-record-type 'point '(x y))`.
+fn make_record_type(vals: &[Value]) -> Result<Value, Error> {
+    let fields = symbols_arg(&vals[1])?;
+    let names = fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+    let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+    Ok(Value::RecordType(RecordType::new(&symbol_arg(&vals[0])?.to_string(), &names)))
+}
+
+/// `(record-constructor type [fields])` takes all the fields by default.
+fn record_constructor(vals: &[Value]) -> Result<Value, Error> {
+    let ty = Rc::<RecordType>::from_lisp(&vals[0])?;
+    let fields = match vals.get(1) {
+        Some(fields) => symbols_arg(fields)?,
+        None => ty.fields.clone(),
+    };
+    Ok(proc(ty.constructor(&format!("make-{}", ty.name), &fields)?))
+}
+
+fn record_predicate(vals: &[Value]) -> Result<Value, Error> {
+    let ty = Rc::<RecordType>::from_lisp(&vals[0])?;
+    Ok(proc(ty.predicate(&format!("{}?", ty.name))))
+}
+
+fn record_accessor(vals: &[Value]) -> Result<Value, Error> {
+    let ty = Rc::<RecordType>::from_lisp(&vals[0])?;
+    let field = symbol_arg(&vals[1])?;
+    Ok(proc(ty.accessor(&format!("{}-{}", ty.name, field), field)?))
+}
+
+fn record_modifier(vals: &[Value]) -> Result<Value, Error> {
+    let ty = Rc::<RecordType>::from_lisp(&vals[0])?;
+    let field = symbol_arg(&vals[1])?;
+    Ok(proc(ty.modifier(&format!("set-{}-{}!", ty.name, field), field)?))
+}
+
+fn is_record(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Record(_))))
+}
+
+fn record_type_descriptor(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::RecordType(Rc::clone(&Rc::<Record>::from_lisp(&vals[0])?.ty)))
+}
+
+fn record_type_name(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Symbol(Symbol::intern(&Rc::<RecordType>::from_lisp(&vals[0])?.name)))
+}
+
+fn record_type_fields(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::list(Rc::<RecordType>::from_lisp(&vals[0])?.fields.iter().map(|&f| Value::Symbol(f)).collect()))
+}
+
+#[test]
+fn record_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(&format!(
+            "(define-record-type point (make-point x y) point? (x point-x set-point-x!) (y point-y)) {}", txt))
+    }
+    assert_eq!(s("(define p (make-point 1 2)) (set-point-x! p 10) (list (point-x p) (point-y p) (point? p) (point? 1))"),
+               s("'(10 2 #t #f)"));
+    assert_eq!(s("(make-point 1 \"a\")").map(|v| v.to_string()), Ok(String::from("#<point x: 1 y: \"a\">")));
+    assert_eq!(s("point").map(|v| v.to_string()), Ok(String::from("#<record-type point>")));
+    assert_eq!(s("(define p (make-point 1 2)) (set-point-x! p (vector p)) p").map(|v| v.to_string()),
+               Ok(String::from("#<point x: #(#<cycle>) y: 2>")));
+    assert_eq!(s("(define p (make-point 1 2)) (set-point-x! p p) (define q (make-point 1 2)) (set-point-x! q q)
+                  (list (equal? p q) (equal? p (make-point p 3)))"), s("'(#t #f)"));
+    assert_eq!(s("(list (equal? (make-point 1 '(2)) (make-point 1 (list 2))) (eqv? (make-point 1 2) (make-point 1 2))
+                        (equal? (make-point 1 2) (make-point 2 1)))"),
+               s("'(#t #f #f)"));
+    assert_eq!(s("(define-record-type <pare> (kons x y) pare? (x kar) (y kdr)) (define p (kons 1 2))
+                  (list (kar p) (kdr p) (pare? p) (point? p) (equal? p (make-point 1 2)))"),
+               s("'(1 2 #t #f #f)"));
+    assert_eq!(s("(define-record-type node (leaf value) node? (value node-value) (children node-children))
+                  (node-children (leaf 1))"), Ok(Value::Unit));
+    assert_eq!(s("(define (norm2 x y) (define-record-type v (make-v x y) v? (x vx) (y vy))
+                    (define p (make-v x y)) (+ (* (vx p) (vx p)) (* (vy p) (vy p))))
+                  (norm2 3 4)"), Ok(Value::Int(25)));
+    assert_eq!(s("(define p (make-point 1 2)) (list (record? p) (record? '(1)) (record-type-name (record-type-descriptor p))
+                        (record-type-fields point) (eq? (record-type-descriptor p) point))"),
+               s("'(#t #f point (x y) #t)"));
+    assert_eq!(s("((record-accessor point 'y) ((record-constructor point '(y x)) 1 2))"), Ok(Value::Int(1)));
+    assert_eq!(s("(define record-constructor 1) (define (record-accessor . args) 'x) (define vector 2)
+                  (define-record-type pair (kons x y) pair? (x kar) (y kdr)) (kar (kons 1 2))"), Ok(Value::Int(1)));
+
+    assert_eq!(s("(point-x '(1 2))"), Err(Error::Type(String::from("expected point, got (1 2)"))));
+    assert_eq!(s("(make-point 1)"), Err(Error::Arity(String::from("#<procedure make-point> expects 2, got 1"))));
+    assert_eq!(s("(record-accessor point 'z)"), Err(Error::Type(String::from("point has no field z"))));
+    assert!(s("(define-record-type bad (make-bad z) bad? (x bad-x))").is_err());
+}
+
+#[test]
+fn host_record_test() {
+    use interpreter::Interpreter;
+
+    let mut interp = Interpreter::new();
+    let ty = RecordType::new("<user>", &["name", "age"]);
+    interp.define_record_type(&ty);
+    let user = ty.make(vec![Value::Str("ann".into()), Value::Int(41)]).unwrap();
+    interp.define("u", user.clone());
+    assert_eq!(interp.eval_str("(set-user-age! u (+ (user-age u) 1)) (list (user? u) (user-name u))"),
+               Ok(Value::list(vec![Value::Bool(true), Value::Str("ann".into())])));
+    let user = Rc::<Record>::from_lisp(&user).unwrap();
+    assert_eq!(user.get("age"), Some(Value::Int(42)));
+    assert_eq!(user.get("email"), None);
+    assert_eq!(interp.eval_str("(make-user \"bo\" 7)").map(|v| v.to_string()),
+               Ok(String::from("#<user name: \"bo\" age: 7>")));
+    assert_eq!(interp.eval_str("(eq? user (record-type-descriptor u))"), Ok(Value::Bool(true)));
+}
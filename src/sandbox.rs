@@ -0,0 +1,93 @@
+use std::path::{Component, Path, PathBuf};
+use error::Error;
+
+/// What scripts run by an interpreter may reach outside of it. A profile
+/// decides which groups of builtins are installed at all, so a sandboxed
+/// script does not get an error from `system`: it has no `system`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Profile {
+    /// Deterministic computation only.
+    Pure,
+    /// Everything that stays inside the process, such as the clock.
+    NoIo,
+    /// Adds the console and reading files below the given root.
+    ReadOnly(PathBuf),
+    /// Everything: writing files, environment variables and commands.
+    Full,
+}
+
+/// A set of related builtins that is installed or left out as a whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    Core,
+    Clock,
+    Console,
+    FsRead,
+    FsWrite,
+    Process,
+}
+
+impl Profile {
+    pub fn allows(&self, group: Group) -> bool {
+        match *self {
+            Profile::Pure => group == Group::Core,
+            Profile::NoIo => matches!(group, Group::Core | Group::Clock),
+            Profile::ReadOnly(_) => !matches!(group, Group::FsWrite | Group::Process),
+            Profile::Full => true,
+        }
+    }
+
+    pub(crate) fn fs(&self) -> Fs {
+        match *self {
+            Profile::ReadOnly(ref root) => Fs { root: Some(root.clone()) },
+            _ => Fs { root: None },
+        }
+    }
+}
+
+/// How file names given by a script are turned into paths.
+#[derive(Debug, Clone)]
+pub struct Fs {
+    root: Option<PathBuf>,
+}
+
+impl Fs {
+    /// Resolves `path` against the root, if there is one, refusing any path
+    /// that ends up outside of it, whether through `..` or a symlink.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf, Error> {
+        let path = path.as_ref();
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return Ok(path.to_path_buf()),
+        };
+        let denied = || Error::Io(format!("{}: outside of the sandbox", path.display()));
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let mut resolved = root.clone();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                Component::ParentDir if resolved != *root => { resolved.pop(); }
+                _ => return Err(denied()),
+            }
+        }
+        if let (Ok(real), Ok(real_root)) = (resolved.canonicalize(), root.canonicalize()) {
+            if !real.starts_with(real_root) {
+                return Err(denied());
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+#[test]
+fn resolve_test() {
+    let root = ::std::env::temp_dir();
+    let fs = Profile::ReadOnly(root.clone()).fs();
+    assert_eq!(fs.resolve("a/b.lisp"), Ok(root.join("a/b.lisp")));
+    assert_eq!(fs.resolve("a/../b.lisp"), Ok(root.join("b.lisp")));
+    assert_eq!(fs.resolve(root.join("c").to_str().unwrap()), Ok(root.join("c")));
+    assert!(fs.resolve("../etc/passwd").is_err());
+    assert!(fs.resolve("/etc/passwd").is_err());
+    assert_eq!(Profile::Full.fs().resolve("/etc/passwd"), Ok(PathBuf::from("/etc/passwd")));
+}
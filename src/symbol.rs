@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// An interned name. Two symbols with the same name are the same `u32`,
+/// so comparing and hashing them never touches the string.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+struct SymbolTable {
+    names: Vec<Rc<str>>,
+    ids: HashMap<Rc<str>, Symbol>,
+}
+
+macro_rules! keywords {
+    ($($name:ident = $text:expr,)*) => {
+        /// Symbols the evaluator looks for; they are interned first, in this
+        /// order, so they can be compared against without a table lookup.
+        pub mod kw {
+            use super::Symbol;
+            keywords!(@consts 0, $($name,)*);
+        }
+
+        const KEYWORDS: &[&str] = &[$($text,)*];
+    };
+    (@consts $n:expr, $name:ident, $($rest:ident,)*) => {
+        pub const $name: Symbol = Symbol($n);
+        keywords!(@consts $n + 1, $($rest,)*);
+    };
+    (@consts $n:expr,) => {};
+}
+
+keywords! {
+    QUOTE = "quote",
+    DEFINE = "define",
+    LAMBDA = "lambda",
+    IF = "if",
+    AND = "and",
+    OR = "or",
+    BEGIN = "begin",
+    SET = "set!",
+    TRUE = "true",
+    FALSE = "false",
+    UNIT = "unit",
+    MODULE = "module",
+    DEFINE_LIBRARY = "define-library",
+    IMPORT = "import",
+    GUARD = "guard",
+    TRY = "try",
+    CATCH = "catch",
+    ELSE = "else",
+    DELAY = "delay",
+    DELAY_FORCE = "delay-force",
+    STREAM_CONS = "stream-cons",
+    DEFINE_RECORD_TYPE = "define-record-type",
+    QUASIQUOTE = "quasiquote",
+    UNQUOTE = "unquote",
+    UNQUOTE_SPLICING = "unquote-splicing",
+    MATCH = "match",
+    WHEN = "when",
+    WILDCARD = "_",
+    PREDICATE = "?",
+    RECORD = "$",
+}
+
+thread_local! {
+    static SYMBOLS: RefCell<SymbolTable> = RefCell::new(SymbolTable::new());
+}
+
+impl SymbolTable {
+    fn new() -> SymbolTable {
+        let mut table = SymbolTable { names: Vec::new(), ids: HashMap::new() };
+        for name in KEYWORDS {
+            table.intern(name);
+        }
+        table
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&sym) = self.ids.get(name) {
+            return sym;
+        }
+        let sym = self.fresh(name.into());
+        self.ids.insert(Rc::clone(&self.names[sym.0 as usize]), sym);
+        sym
+    }
+
+    fn fresh(&mut self, name: Rc<str>) -> Symbol {
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(name);
+        sym
+    }
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        SYMBOLS.with(|t| t.borrow_mut().intern(name))
+    }
+
+    /// Creates an uninterned symbol: it prints as `prefix` followed by a
+    /// number, but is distinct from every other symbol, including one later
+    /// interned under the same name.
+    pub fn gensym(prefix: &str) -> Symbol {
+        SYMBOLS.with(|t| {
+            let mut t = t.borrow_mut();
+            let name = format!("{}{}", prefix, t.names.len());
+            t.fresh(name.into())
+        })
+    }
+
+    pub fn name(self) -> Rc<str> {
+        SYMBOLS.with(|t| Rc::clone(&t.borrow().names[self.0 as usize]))
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({})", self.name())
+    }
+}
+
+#[test]
+fn symbol_test() {
+    assert_eq!(Symbol::intern("quote"), kw::QUOTE);
+    assert_eq!(Symbol::intern("abc"), Symbol::intern("abc"));
+    assert!(Symbol::intern("abc") != Symbol::intern("abd"));
+    assert_eq!(&*Symbol::intern("abc").name(), "abc");
+
+    let g = Symbol::gensym("g");
+    assert!(Symbol::intern(&g.name()) != g);
+    assert!(Symbol::gensym("g") != g);
+}
@@ -0,0 +1,165 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use builtins::{register, string_arg, Arity, Builtin};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::eval;
+use parser::read_lines;
+use port::{optional_port, Console};
+use sandbox::{Fs, Group, Profile};
+use value::Value;
+
+type ConsoleFn = fn(&Console, &[Value]) -> Result<Value, Error>;
+
+const CONSOLE: &[(&str, Arity, ConsoleFn)] = &[
+    ("display", Arity::Range(1, 2), display),
+    ("write", Arity::Range(1, 2), write),
+    ("newline", Arity::Range(0, 1), newline),
+    ("print", Arity::AtLeast(0), print),
+    ("println", Arity::AtLeast(0), println),
+    ("read-line", Arity::Range(0, 1), read_line),
+    ("read", Arity::Range(0, 1), read),
+];
+
+/// Binds the builtins that reach outside of the interpreter, as far as
+/// `profile` allows. Console builtins use the ports in `console`.
+pub fn install(env: &mut Env, profile: &Profile, console: &Rc<Console>) {
+    if profile.allows(Group::Console) {
+        for &(name, arity, func) in CONSOLE {
+            let console = Rc::clone(console);
+            register(env, Builtin::new(name, move |args| func(&console, args)).with_arity(arity));
+        }
+    }
+    if profile.allows(Group::Clock) {
+        register(env, Builtin::new("current-time", current_time).with_arity(Arity::Exact(0))
+            .with_doc("Seconds since the Unix epoch, as a float."));
+    }
+    let fs = Rc::new(profile.fs());
+    if profile.allows(Group::FsRead) {
+        let exists = Rc::clone(&fs);
+        register(env, Builtin::new("file-exists?", move |args| file_exists(&exists, args))
+            .with_arity(Arity::Exact(1)));
+        let fs = Rc::clone(&fs);
+        register(env, Builtin::with_context("load", move |ctx, args| load_builtin(&fs, ctx, args))
+            .with_arity(Arity::Exact(1))
+            .with_doc("Evaluates a file, found relative to the file being loaded, if any."));
+    }
+    if profile.allows(Group::FsWrite) {
+        let fs = Rc::clone(&fs);
+        register(env, Builtin::new("delete-file", move |args| delete_file(&fs, args))
+            .with_arity(Arity::Exact(1)));
+    }
+    if profile.allows(Group::Process) {
+        register(env, Builtin::new("getenv", getenv).with_arity(Arity::Exact(1))
+            .with_doc("The value of an environment variable, or #f if it is not set."));
+        register(env, Builtin::new("system", system).with_arity(Arity::Exact(1))
+            .with_doc("Runs a shell command and returns its exit status."));
+    }
+}
+
+fn display(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    optional_port(args, 1, || console.output())?.write_str(&format!("{:#}", args[0]))?;
+    Ok(Value::Unit)
+}
+
+fn write(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    optional_port(args, 1, || console.output())?.write_str(&args[0].to_string())?;
+    Ok(Value::Unit)
+}
+
+fn newline(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    optional_port(args, 0, || console.output())?.write_str("\n")?;
+    Ok(Value::Unit)
+}
+
+/// `(print a b ...)` displays its arguments separated by spaces.
+fn print(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    let text = args.iter().map(|v| format!("{:#}", v)).collect::<Vec<_>>().join(" ");
+    console.output().write_str(&text)?;
+    Ok(Value::Unit)
+}
+
+fn println(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    print(console, args)?;
+    newline(console, &[])
+}
+
+fn read_line(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(optional_port(args, 0, || console.input())?.read_line()?.map_or(Value::Eof, |line| Value::Str(line.into())))
+}
+
+fn read(console: &Console, args: &[Value]) -> Result<Value, Error> {
+    Ok(optional_port(args, 0, || console.input())?.read_datum()?.map_or(Value::Eof, |datum| Value::from(&datum)))
+}
+
+/// Evaluates the file at `path` in the global environment and returns the
+/// value of its last form. Errors are attributed to the line of the
+/// top-level form they come from.
+pub fn load(path: &Path, ctx: &Context) -> Result<Value, Error> {
+    let name = path.display().to_string();
+    if ctx.loading.borrow().iter().any(|p| same_file(p, path)) {
+        let mut chain = ctx.loading.borrow().iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+        chain.push(name);
+        return Err(Error::Io(format!("load cycle: {}", chain.join(" -> "))));
+    }
+    let src = fs::read_to_string(path).map_err(|e| Error::Io(format!("{}: {}", name, e)))?;
+    let data = read_lines(&src).map_err(|e| match e {
+        Error::Parse(msg) => Error::Parse(format!("{}: {}", name, msg)),
+        e => e,
+    })?;
+    let env = ctx.env();
+    ctx.loading.borrow_mut().push(path.to_path_buf());
+    let result = data.iter().try_fold(Value::Unit, |_, &(line, ref expr)| {
+        eval(expr, &env, ctx).map_err(|e| e.located(&name, line))
+    });
+    ctx.loading.borrow_mut().pop();
+    result
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn load_builtin(fs: &Fs, ctx: &Context, args: &[Value]) -> Result<Value, Error> {
+    let name = Path::new(string_arg(&args[0])?);
+    let path = match ctx.loading.borrow().last().and_then(|p| p.parent()) {
+        Some(dir) if name.is_relative() => dir.join(name),
+        _ => name.to_path_buf(),
+    };
+    load(&fs.resolve(path)?, ctx)
+}
+
+fn current_time(_: &[Value]) -> Result<Value, Error> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(Value::Float(now.as_secs_f64()))
+}
+
+fn file_exists(fs: &Fs, args: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(fs.resolve(string_arg(&args[0])?)?.exists()))
+}
+
+fn delete_file(fs: &Fs, args: &[Value]) -> Result<Value, Error> {
+    let name = string_arg(&args[0])?;
+    fs::remove_file(fs.resolve(name)?)
+        .map_err(|e| Error::Io(format!("{}: {}", name, e)))?;
+    Ok(Value::Unit)
+}
+
+fn getenv(args: &[Value]) -> Result<Value, Error> {
+    Ok(env::var(string_arg(&args[0])?).map_or(Value::Bool(false), |v| Value::Str(v.into())))
+}
+
+fn system(args: &[Value]) -> Result<Value, Error> {
+    let command = string_arg(&args[0])?;
+    let status = Command::new("sh").arg("-c").arg(command).status()
+        .map_err(|e| Error::Io(format!("{}: {}", command, e)))?;
+    Ok(status.code().map_or(Value::Bool(false), |c| Value::Int(i64::from(c))))
+}
@@ -0,0 +1,488 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use builtins::Builtin;
+use compile::Lambda;
+use continuation::Continuation;
+use env::{Env, Frame};
+use error::Error;
+use expr::Expr;
+use foreign::Foreign;
+use generator::Generator;
+use hash_table::Table;
+use parser::MAX_NESTING;
+use persistent::{Map, PVector, Set};
+use port::Port;
+use promise::Promise;
+use record::{Record, RecordType};
+use symbol::Symbol;
+
+/// A runtime value, as produced by evaluation.
+#[derive(Clone)]
+pub enum Value {
+    Unit,
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    Symbol(Symbol),
+    Pair(Rc<Pair>),
+    Vector(Rc<RefCell<Vec<Value>>>),
+    Hash(Rc<RefCell<Table>>),
+    Map(Map),
+    Set(Set),
+    PVector(PVector),
+    Record(Rc<Record>),
+    RecordType(Rc<RecordType>),
+    Closure(Rc<Closure>),
+    Builtin(Rc<Builtin>),
+    Foreign(Rc<Foreign>),
+    Port(Rc<Port>),
+    Environment(Rc<RefCell<Env>>),
+    /// An error caught by `guard` or an exception handler.
+    Error(Rc<Error>),
+    Continuation(Rc<Continuation>),
+    Promise(Rc<Promise>),
+    Generator(Rc<Generator>),
+    /// The `yield` procedure of a generator's body.
+    Yield(Rc<Generator>),
+    /// What reading past the end of a port returns.
+    Eof,
+}
+
+pub struct Pair {
+    pub car: Value,
+    pub cdr: Value,
+}
+
+/// Frees the pairs a pair holds one at a time, rather than each pair
+/// dropping the ones it holds, which would take a native frame per element
+/// of a long or deeply nested list.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        fn take(v: &mut Value, pending: &mut Vec<Pair>) {
+            if let Value::Pair(p) = mem::replace(v, Value::Nil) {
+                if let Ok(pair) = Rc::try_unwrap(p) {
+                    pending.push(pair);
+                }
+            }
+        }
+        let mut pending = Vec::new();
+        take(&mut self.car, &mut pending);
+        take(&mut self.cdr, &mut pending);
+        while let Some(mut pair) = pending.pop() {
+            take(&mut pair.car, &mut pending);
+            take(&mut pair.cdr, &mut pending);
+        }
+    }
+}
+
+pub struct Closure {
+    pub lambda: Rc<Lambda>,
+    pub env: Option<Rc<Frame>>,
+}
+
+thread_local! {
+    static CELLS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The number of heap cells allocated on this thread so far; limits compare
+/// it against its value at the start of an evaluation.
+pub fn cells_allocated() -> usize {
+    CELLS.with(Cell::get)
+}
+
+pub fn count_cells(n: usize) {
+    CELLS.with(|c| c.set(c.get() + n))
+}
+
+impl Value {
+    pub fn cons(car: Value, cdr: Value) -> Value {
+        count_cells(1);
+        Value::Pair(Rc::new(Pair { car, cdr }))
+    }
+
+    pub fn list(items: Vec<Value>) -> Value {
+        Value::list_with_tail(items, Value::Nil)
+    }
+
+    pub fn list_with_tail(items: Vec<Value>, tail: Value) -> Value {
+        items.into_iter().rev().fold(tail, |acc, x| Value::cons(x, acc))
+    }
+
+    /// The elements of a proper list, or `None` for anything else.
+    pub fn to_vec(&self) -> Option<Vec<Value>> {
+        let mut items = Vec::new();
+        let mut cur = self;
+        loop {
+            match *cur {
+                Value::Nil => return Some(items),
+                Value::Pair(ref p) => {
+                    items.push(p.car.clone());
+                    cur = &p.cdr;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// Everything except `#f` counts as true.
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Value::Bool(b) => b,
+            _ => true,
+        }
+    }
+
+    /// Identity (`eq?`): booleans, unit, the eof object, symbols, the empty
+    /// list and integers are immediate values and are `eq?` when equal;
+    /// floats are never `eq?`; everything else, such as strings, pairs,
+    /// procedures, ports and promises, is `eq?` only when both sides refer
+    /// to the same allocation.
+    pub fn is_eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Unit, Value::Unit) | (Value::Nil, Value::Nil) | (Value::Eof, Value::Eof) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => Rc::ptr_eq(a, b),
+            (Value::Pair(a), Value::Pair(b)) => Rc::ptr_eq(a, b),
+            (Value::Vector(a), Value::Vector(b)) => Rc::ptr_eq(a, b),
+            (Value::Hash(a), Value::Hash(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => a.is_same(b),
+            (Value::Set(a), Value::Set(b)) => a.is_same(b),
+            (Value::PVector(a), Value::PVector(b)) => a.is_same(b),
+            (Value::Record(a), Value::Record(b)) => Rc::ptr_eq(a, b),
+            (Value::RecordType(a), Value::RecordType(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => Rc::ptr_eq(a, b),
+            (Value::Foreign(a), Value::Foreign(b)) => Rc::ptr_eq(a, b),
+            (Value::Port(a), Value::Port(b)) => Rc::ptr_eq(a, b),
+            (Value::Environment(a), Value::Environment(b)) => Rc::ptr_eq(a, b),
+            (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+            (Value::Continuation(a), Value::Continuation(b)) => Rc::ptr_eq(a, b),
+            (Value::Promise(a), Value::Promise(b)) => Rc::ptr_eq(a, b),
+            (Value::Generator(a), Value::Generator(b)) => Rc::ptr_eq(a, b),
+            (Value::Yield(a), Value::Yield(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// Value equality for atoms (`eqv?`): like `eq?`, except that floats
+    /// are compared by value. Exactness matters, so `(eqv? 2 2.0)` is false
+    /// while `(= 2 2.0)` is true.
+    pub fn is_eqv(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            _ => self.is_eq(other),
+        }
+    }
+
+    /// Structural equality (`equal?`): strings are compared by content,
+    /// pairs, vectors, hash tables, persistent collections and records of
+    /// the same type recursively with `equal?`; everything else, including
+    /// procedures, falls back to `eqv?`. The walk keeps its own stack, so
+    /// long and deeply nested lists are fine, and two vectors, hash tables
+    /// or records met again while they are being compared count as equal,
+    /// so data that contain themselves are too.
+    pub fn is_equal(&self, other: &Value) -> bool {
+        let mut pending = vec![(self.clone(), other.clone())];
+        let mut seen = HashSet::new();
+        // Whether `a` and `b` still need comparing: they are different
+        // containers, and not already being compared further out.
+        let mut fresh = |a: *const (), b: *const ()| a != b && seen.insert((a, b));
+        while let Some((a, b)) = pending.pop() {
+            match (&a, &b) {
+                (Value::Str(x), Value::Str(y)) => if x != y {
+                    return false;
+                },
+                (Value::Pair(x), Value::Pair(y)) => if !Rc::ptr_eq(x, y) {
+                    pending.push((x.cdr.clone(), y.cdr.clone()));
+                    pending.push((x.car.clone(), y.car.clone()));
+                },
+                (Value::Vector(x), Value::Vector(y)) => if fresh(Rc::as_ptr(x) as _, Rc::as_ptr(y) as _) {
+                    let (x, y) = (x.borrow(), y.borrow());
+                    if x.len() != y.len() {
+                        return false;
+                    }
+                    pending.extend(x.iter().cloned().zip(y.iter().cloned()).rev());
+                },
+                (Value::Hash(x), Value::Hash(y)) => if fresh(Rc::as_ptr(x) as _, Rc::as_ptr(y) as _) {
+                    let (x, y) = (x.borrow(), y.borrow());
+                    if x.len() != y.len() {
+                        return false;
+                    }
+                    for (k, v) in x.entries() {
+                        match y.get(k) {
+                            Some(w) => pending.push((v.clone(), w.clone())),
+                            None => return false,
+                        }
+                    }
+                },
+                (Value::Map(x), Value::Map(y)) => if !x.is_same(y) {
+                    if x.len() != y.len() {
+                        return false;
+                    }
+                    for (k, v) in x.entries() {
+                        match y.get(&k) {
+                            Some(w) => pending.push((v, w.clone())),
+                            None => return false,
+                        }
+                    }
+                },
+                (Value::Set(x), Value::Set(y)) => if !x.is_equal(y) {
+                    return false;
+                },
+                (Value::PVector(x), Value::PVector(y)) => if !x.is_same(y) {
+                    if x.len() != y.len() {
+                        return false;
+                    }
+                    pending.extend(x.items().into_iter().zip(y.items()).rev());
+                },
+                (Value::Record(x), Value::Record(y)) => if fresh(Rc::as_ptr(x) as _, Rc::as_ptr(y) as _) {
+                    if !Rc::ptr_eq(&x.ty, &y.ty) {
+                        return false;
+                    }
+                    pending.extend(x.values().into_iter().zip(y.values()).rev());
+                },
+                _ => if !a.is_eqv(&b) {
+                    return false;
+                },
+            }
+        }
+        true
+    }
+}
+
+impl<'a> From<&'a Expr> for Value {
+    fn from(datum: &'a Expr) -> Value {
+        match *datum {
+            Expr::Int(i) => Value::Int(i),
+            Expr::Float(f) => Value::Float(f),
+            Expr::Str(ref s) => Value::Str(s.as_str().into()),
+            Expr::Bool(b) => Value::Bool(b),
+            Expr::Symbol(s) => Value::Symbol(s),
+            Expr::List(ref items) => Value::list(items.iter().map(Value::from).collect()),
+            Expr::DottedList(ref items, ref tail) =>
+                Value::list_with_tail(items.iter().map(Value::from).collect(), Value::from(&**tail)),
+            Expr::Vector(ref items) => Value::vector(items.iter().map(Value::from).collect()),
+            Expr::Hash(ref entries) => Value::hash_table(Table::from_entries(
+                entries.iter().map(|(k, v)| (Value::from(k), Value::from(v))).collect())),
+        }
+    }
+}
+
+impl Value {
+    /// The datum a value stands for when it is treated as code, as by
+    /// `eval`; procedures, ports and the like have none, and data nested
+    /// deeper than the reader accepts are refused too.
+    pub fn to_datum(&self) -> Result<Expr, Error> {
+        self.datum_at(0)
+    }
+
+    fn datum_at(&self, depth: usize) -> Result<Expr, Error> {
+        if depth > MAX_NESTING {
+            return Err(Error::Syntax(format!("code nested more than {} deep", MAX_NESTING)));
+        }
+        let datum = |v: &Value| v.datum_at(depth + 1);
+        match *self {
+            Value::Int(i) => Ok(Expr::Int(i)),
+            Value::Float(f) => Ok(Expr::Float(f)),
+            Value::Str(ref s) => Ok(Expr::Str(s.to_string())),
+            Value::Bool(b) => Ok(Expr::Bool(b)),
+            Value::Symbol(s) => Ok(Expr::Symbol(s)),
+            Value::Nil => Ok(Expr::List(vec![])),
+            Value::Pair(_) => {
+                let mut items = Vec::new();
+                let mut cur = self;
+                while let Value::Pair(ref p) = *cur {
+                    items.push(datum(&p.car)?);
+                    cur = &p.cdr;
+                }
+                match *cur {
+                    Value::Nil => Ok(Expr::List(items)),
+                    ref tail => Ok(Expr::DottedList(items, Box::new(datum(tail)?))),
+                }
+            }
+            Value::Vector(ref items) =>
+                Ok(Expr::Vector(items.borrow().iter().map(datum).collect::<Result<_, _>>()?)),
+            Value::Hash(ref table) => Ok(Expr::Hash(table.borrow().entries().iter()
+                .map(|(k, v)| Ok((datum(k)?, datum(v)?)))
+                .collect::<Result<_, Error>>()?)),
+            ref v => Err(Error::Type(format!("expected a datum, got {}", v))),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.is_equal(other)
+    }
+}
+
+thread_local! {
+    /// The compound values being printed, outermost first: their addresses
+    /// for vectors, hash tables and records, which may contain themselves,
+    /// and null for the rest.
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Values print in the form `write` uses, which the reader can read back
+/// where possible; the alternate form, `{:#}`, is the one `display` uses,
+/// with strings printed as their contents. A vector, hash table or record
+/// found inside itself prints as `#<cycle>`, and anything nested deeper than
+/// the reader accepts as `...`, rather than recursing until the stack
+/// overflows.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ptr = match *self {
+            Value::Vector(ref v) => Rc::as_ptr(v) as *const (),
+            Value::Hash(ref t) => Rc::as_ptr(t) as *const (),
+            Value::Record(ref r) => Rc::as_ptr(r) as *const (),
+            Value::Pair(_) | Value::Map(_) | Value::Set(_) | Value::PVector(_) => ptr::null(),
+            _ => return self.write(f),
+        };
+        let seen = PRINTING.with(|printing| {
+            let printing = printing.borrow();
+            if !ptr.is_null() && printing.contains(&ptr) {
+                Some("#<cycle>")
+            } else if printing.len() >= MAX_NESTING {
+                Some("...")
+            } else {
+                None
+            }
+        });
+        if let Some(seen) = seen {
+            return write!(f, "{}", seen);
+        }
+        PRINTING.with(|printing| printing.borrow_mut().push(ptr));
+        let result = self.write(f);
+        PRINTING.with(|printing| printing.borrow_mut().pop());
+        result
+    }
+}
+
+impl Value {
+    fn write(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let human = f.alternate();
+        let show = |f: &mut fmt::Formatter, v: &Value| if human { write!(f, "{:#}", v) } else { write!(f, "{}", v) };
+        match *self {
+            Value::Unit => write!(f, "unit"),
+            Value::Nil => write!(f, "()"),
+            Value::Bool(true) => write!(f, "#t"),
+            Value::Bool(false) => write!(f, "#f"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) if x.is_nan() => write!(f, "+nan.0"),
+            Value::Float(x) if x.is_infinite() => write!(f, "{}inf.0", if x > 0.0 { "+" } else { "-" }),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Str(ref s) if human => write!(f, "{}", s),
+            Value::Str(ref s) => write!(f, "{:?}", s),
+            Value::Symbol(s) => write!(f, "{}", s),
+            Value::Pair(ref p) => {
+                write!(f, "(")?;
+                show(f, &p.car)?;
+                let mut cur = &p.cdr;
+                loop {
+                    match *cur {
+                        Value::Nil => break,
+                        Value::Pair(ref p) => {
+                            write!(f, " ")?;
+                            show(f, &p.car)?;
+                            cur = &p.cdr;
+                        }
+                        ref tail => {
+                            write!(f, " . ")?;
+                            show(f, tail)?;
+                            break;
+                        }
+                    }
+                }
+                write!(f, ")")
+            }
+            Value::Vector(ref items) => {
+                write!(f, "#(")?;
+                for (n, item) in items.borrow().iter().enumerate() {
+                    if n > 0 {
+                        write!(f, " ")?;
+                    }
+                    show(f, item)?;
+                }
+                write!(f, ")")
+            }
+            Value::Hash(ref table) => {
+                write!(f, "#hash(")?;
+                for (n, (k, v)) in table.borrow().entries().iter().enumerate() {
+                    if n > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "(")?;
+                    show(f, k)?;
+                    write!(f, " . ")?;
+                    show(f, v)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ")")
+            }
+            Value::Map(ref m) => {
+                write!(f, "#<map")?;
+                for (k, v) in m.entries() {
+                    write!(f, " (")?;
+                    show(f, &k)?;
+                    write!(f, " . ")?;
+                    show(f, &v)?;
+                    write!(f, ")")?;
+                }
+                write!(f, ">")
+            }
+            Value::Set(ref s) => {
+                write!(f, "#<set")?;
+                for item in s.items() {
+                    write!(f, " ")?;
+                    show(f, &item)?;
+                }
+                write!(f, ">")
+            }
+            Value::PVector(ref v) => {
+                write!(f, "#<pvector")?;
+                for item in v.items() {
+                    write!(f, " ")?;
+                    show(f, &item)?;
+                }
+                write!(f, ">")
+            }
+            Value::Record(ref r) => {
+                write!(f, "#<{}", r.ty.name)?;
+                for (field, value) in r.ty.fields.iter().zip(r.values()) {
+                    write!(f, " {}: ", field)?;
+                    show(f, &value)?;
+                }
+                write!(f, ">")
+            }
+            Value::RecordType(ref ty) => write!(f, "#<record-type {}>", ty.name),
+            Value::Closure(ref c) => match c.lambda.name {
+                Some(name) => write!(f, "#<procedure {}>", name),
+                None => write!(f, "#<procedure>"),
+            },
+            Value::Builtin(ref b) => write!(f, "#<procedure {}>", b.name),
+            Value::Foreign(ref obj) => write!(f, "#<{}>", obj.ty.name),
+            Value::Port(ref port) => write!(f, "#<{} {}>", port.kind(), port.name),
+            Value::Environment(_) => write!(f, "#<environment>"),
+            Value::Error(ref e) => write!(f, "#<error {:?}>", e.message()),
+            Value::Continuation(_) => write!(f, "#<continuation>"),
+            Value::Promise(_) => write!(f, "#<promise>"),
+            Value::Generator(_) => write!(f, "#<generator>"),
+            Value::Yield(_) => write!(f, "#<procedure yield>"),
+            Value::Eof => write!(f, "#<eof>"),
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
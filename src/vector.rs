@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use builtins::{register, type_error, Arity, Builtin, Control, Primitive};
+use context::Context;
+use env::Env;
+use error::Error;
+use eval::apply;
+use value::{count_cells, Value};
+
+const PRIMITIVES: &[(&str, Arity, Primitive)] = &[
+    ("vector", Arity::AtLeast(0), vector),
+    ("make-vector", Arity::Range(1, 2), make_vector),
+    ("vector?", Arity::Exact(1), is_vector),
+    ("vector-length", Arity::Exact(1), vector_length),
+    ("vector-ref", Arity::Exact(2), vector_ref),
+    ("vector-set!", Arity::Exact(3), vector_set),
+    ("vector->list", Arity::Range(1, 3), vector_to_list),
+    ("list->vector", Arity::Exact(1), list_to_vector),
+    ("vector-fill!", Arity::Range(2, 4), vector_fill),
+    ("vector-copy", Arity::Range(1, 3), vector_copy),
+    ("subvector", Arity::Exact(3), vector_copy),
+    ("vector-append", Arity::AtLeast(0), vector_append),
+];
+
+const CONTROL: &[(&str, Arity, Control)] = &[
+    ("vector-map", Arity::AtLeast(2), vector_map),
+    ("vector-for-each", Arity::AtLeast(2), vector_for_each),
+];
+
+pub fn install(env: &mut Env) {
+    for &(name, arity, func) in PRIMITIVES {
+        register(env, Builtin::new(name, func).with_arity(arity));
+    }
+    for &(name, arity, func) in CONTROL {
+        register(env, Builtin::with_context(name, func).with_arity(arity));
+    }
+}
+
+impl Value {
+    pub fn vector(items: Vec<Value>) -> Value {
+        count_cells(items.len());
+        Value::Vector(Rc::new(RefCell::new(items)))
+    }
+}
+
+fn vector_arg(v: &Value) -> Result<&RefCell<Vec<Value>>, Error> {
+    match *v {
+        Value::Vector(ref items) => Ok(items),
+        ref v => Err(type_error("vector", v)),
+    }
+}
+
+fn size_arg(v: &Value) -> Result<usize, Error> {
+    match *v {
+        Value::Int(i) if i >= 0 => Ok(i as usize),
+        ref v => Err(type_error("non-negative integer", v)),
+    }
+}
+
+/// An index of an element of a vector of length `len`.
+fn index_arg(v: &Value, len: usize) -> Result<usize, Error> {
+    let i = size_arg(v)?;
+    if i < len {
+        Ok(i)
+    } else {
+        Err(Error::Range(format!("index {} of a vector of length {}", i, len)))
+    }
+}
+
+/// The `start` and `end` arguments at `vals[from..]`, which default to the
+/// whole of a vector of length `len`.
+fn range_args(vals: &[Value], from: usize, len: usize) -> Result<(usize, usize), Error> {
+    let start = vals.get(from).map_or(Ok(0), size_arg)?;
+    let end = vals.get(from + 1).map_or(Ok(len), size_arg)?;
+    if start <= end && end <= len {
+        Ok((start, end))
+    } else {
+        Err(Error::Range(format!("{} to {} of a vector of length {}", start, end, len)))
+    }
+}
+
+fn vector(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::vector(vals.to_vec()))
+}
+
+fn make_vector(vals: &[Value]) -> Result<Value, Error> {
+    let fill = vals.get(1).cloned().unwrap_or(Value::Unit);
+    let size = size_arg(&vals[0])?;
+    let mut items = Vec::new();
+    if items.try_reserve_exact(size).is_err() {
+        return Err(Error::Range(format!("cannot allocate a vector of length {}", size)));
+    }
+    items.resize(size, fill);
+    Ok(Value::vector(items))
+}
+
+fn is_vector(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Bool(matches!(vals[0], Value::Vector(_))))
+}
+
+fn vector_length(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::Int(vector_arg(&vals[0])?.borrow().len() as i64))
+}
+
+fn vector_ref(vals: &[Value]) -> Result<Value, Error> {
+    let items = vector_arg(&vals[0])?.borrow();
+    Ok(items[index_arg(&vals[1], items.len())?].clone())
+}
+
+fn vector_set(vals: &[Value]) -> Result<Value, Error> {
+    let mut items = vector_arg(&vals[0])?.borrow_mut();
+    let i = index_arg(&vals[1], items.len())?;
+    items[i] = vals[2].clone();
+    Ok(Value::Unit)
+}
+
+fn vector_to_list(vals: &[Value]) -> Result<Value, Error> {
+    let items = vector_arg(&vals[0])?.borrow();
+    let (start, end) = range_args(vals, 1, items.len())?;
+    Ok(Value::list(items[start..end].to_vec()))
+}
+
+fn list_to_vector(vals: &[Value]) -> Result<Value, Error> {
+    Ok(Value::vector(vals[0].to_vec().ok_or_else(|| type_error("list", &vals[0]))?))
+}
+
+/// `(vector-fill! v x [start [end]])`.
+fn vector_fill(vals: &[Value]) -> Result<Value, Error> {
+    let mut items = vector_arg(&vals[0])?.borrow_mut();
+    let (start, end) = range_args(vals, 2, items.len())?;
+    for item in &mut items[start..end] {
+        *item = vals[1].clone();
+    }
+    Ok(Value::Unit)
+}
+
+/// `(vector-copy v [start [end]])` is a new vector with the elements of `v`
+/// from `start` up to, but not including, `end`.
+fn vector_copy(vals: &[Value]) -> Result<Value, Error> {
+    let items = vector_arg(&vals[0])?.borrow();
+    let (start, end) = range_args(vals, 1, items.len())?;
+    Ok(Value::vector(items[start..end].to_vec()))
+}
+
+fn vector_append(vals: &[Value]) -> Result<Value, Error> {
+    let mut result = Vec::new();
+    for v in vals {
+        result.extend(vector_arg(v)?.borrow().iter().cloned());
+    }
+    Ok(Value::vector(result))
+}
+
+/// The elements of the vectors at `vals[1..]`, up to the length of the
+/// shortest one, as argument lists for the procedure at `vals[0]`.
+fn spread(vals: &[Value]) -> Result<Vec<Vec<Value>>, Error> {
+    let vectors = vals[1..].iter()
+        .map(|v| vector_arg(v).map(|items| items.borrow().clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = vectors.iter().map(Vec::len).min().unwrap_or(0);
+    Ok((0..len).map(|i| vectors.iter().map(|v| v[i].clone()).collect()).collect())
+}
+
+fn vector_map(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    let result = spread(vals)?.into_iter()
+        .map(|args| apply(&vals[0], args, ctx))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::vector(result))
+}
+
+fn vector_for_each(ctx: &Context, vals: &[Value]) -> Result<Value, Error> {
+    for args in spread(vals)? {
+        apply(&vals[0], args, ctx)?;
+    }
+    Ok(Value::Unit)
+}
+
+#[test]
+fn vector_test() {
+    use interpreter::Interpreter;
+
+    fn s(txt: &str) -> Result<Value, Error> {
+        Interpreter::new().eval_str(txt)
+    }
+    assert_eq!(s("#(1 \"a\" (b))").map(|v| v.to_string()), Ok(String::from("#(1 \"a\" (b))")));
+    assert_eq!(s("(vector-ref #(1 2 3) 1)"), Ok(Value::Int(2)));
+    assert_eq!(s("(define v (make-vector 3 0)) (vector-set! v 0 'x) v"), s("#(x 0 0)"));
+    assert_eq!(s("(list (vector-length #()) (vector-length (make-vector 4)) (vector? #(1)) (vector? '(1)))"),
+               s("'(0 4 #t #f)"));
+    assert_eq!(s("(list (vector->list #(1 2 3)) (vector->list #(1 2 3) 1) (vector->list #(1 2 3) 1 2))"),
+               s("'((1 2 3) (2 3) (2))"));
+    assert_eq!(s("(list->vector '(1 2))"), s("(vector 1 2)"));
+    assert_eq!(s("(vector-map + #(1 2 3) #(10 20))"), s("#(11 22)"));
+    assert_eq!(s("(define total 0) (vector-for-each (lambda (x) (set! total (+ total x))) #(1 2 3)) total"),
+               Ok(Value::Int(6)));
+    assert_eq!(s("(define v (vector 1 2 3 4)) (vector-fill! v 0 1 3) v"), s("#(1 0 0 4)"));
+    assert_eq!(s("(list (vector-copy #(1 2 3) 1) (subvector #(1 2 3) 0 2) (vector-append #(1) #() #(2 3)))"),
+               s("'(#(2 3) #(1 2) #(1 2 3))"));
+    assert_eq!(s("(define v #(1 2)) (define w (vector-copy v)) (vector-set! w 0 9) v"), s("#(1 2)"));
+    assert_eq!(s("(list (equal? #(1 (2)) (vector 1 (list 2))) (eqv? #(1) #(1)) (equal? #(1) '(1)))"),
+               s("'(#t #f #f)"));
+    assert_eq!(s("(eval (vector-ref #((+ 1 2)) 0))"), Ok(Value::Int(3)));
+    assert_eq!(s("(read-from-string \"#(a b)\")"), s("'#(a b)"));
+    assert_eq!(s("(define v (vector 1 2)) (vector-set! v 0 (list v)) v").map(|v| v.to_string()),
+               Ok(String::from("#((#<cycle>) 2)")));
+    assert_eq!(s("(define v (vector 1 1)) (vector-set! v 0 v) (define w (vector 1 1)) (vector-set! w 0 w)
+                  (define x (vector 1 2)) (vector-set! x 0 x)
+                  (list (equal? v w) (equal? v x) (equal? (list v) (list w)))"), s("'(#t #f #t)"));
+    assert_eq!(s("(define v (vector 1)) (list v v)").map(|v| v.to_string()), Ok(String::from("(#(1) #(1))")));
+    assert_eq!(s("(define (nest n xs) (if (= n 0) xs (nest (- n 1) (vector xs)))) (nest 2000 1)")
+                   .map(|v| v.to_string().contains("#(#(...))")),
+               Ok(true));
+
+    assert_eq!(s("(make-vector 4611686018427387903 0)"),
+               Err(Error::Range(String::from("cannot allocate a vector of length 4611686018427387903"))));
+    assert_eq!(s("(vector-ref #(1 2 3) 3)"),
+               Err(Error::Range(String::from("index 3 of a vector of length 3"))));
+    assert_eq!(s("(vector-set! (vector) 0 1)"),
+               Err(Error::Range(String::from("index 0 of a vector of length 0"))));
+    assert_eq!(s("(vector-copy #(1 2) 2 1)"), Err(Error::Range(String::from("2 to 1 of a vector of length 2"))));
+    assert_eq!(s("(vector-ref #(1) -1)"), Err(Error::Type(String::from("expected non-negative integer, got -1"))));
+    assert_eq!(s("(vector-ref '(1) 0)"), Err(Error::Type(String::from("expected vector, got (1)"))));
+}
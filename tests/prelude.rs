@@ -0,0 +1,119 @@
+extern crate mlisp;
+
+use mlisp::{Error, Interpreter, Profile, Symbol, Value};
+
+fn s(txt: &str) -> Result<Value, Error> {
+    Interpreter::new().eval_str(txt)
+}
+
+fn list(items: &[i64]) -> Value {
+    Value::list(items.iter().map(|&i| Value::Int(i)).collect())
+}
+
+#[test]
+fn logic() {
+    assert_eq!(s("(list (not #f) (not 0) (and) (and 1 2) (and 1 #f 2) (or) (or #f 3) (or #f #f))"),
+               s("'(#t #f #t 2 #f #f 3 #f)"));
+    assert_eq!(s("(define (f x) (and (pair? x) (car x))) (list (f 1) (f '(2)))"), s("'(#f 2)"));
+    assert_eq!(s("(define (g x) (or (not (pair? x)) (car x))) (list (g 1) (g '(2)))"), s("'(#t 2)"));
+    assert_eq!(s("(define n 0) (define (bump) (set! n (+ n 1)) n) (list (or (bump) (bump)) (and #f (bump)) n)"),
+               s("'(1 #f 1)"));
+    assert_eq!(s("(define (last-true xs) (or (null? (cdr xs)) (last-true (cdr xs)))) (last-true (range 0 100000))"),
+               Ok(Value::Bool(true)));
+}
+
+#[test]
+fn numbers() {
+    assert_eq!(s("(list (add1 1) (sub1 1) (zero? 0) (zero? 1))"), s("'(2 0 #t #f)"));
+}
+
+#[test]
+fn lists() {
+    assert_eq!(s("(length '(a b c))"), Ok(Value::Int(3)));
+    assert_eq!(s("(length '())"), Ok(Value::Int(0)));
+    assert_eq!(s("(reverse '(1 2 3))"), Ok(list(&[3, 2, 1])));
+    assert_eq!(s("(concat '(1) '() '(2 3))"), Ok(list(&[1, 2, 3])));
+    assert_eq!(s("(list (cadr '(1 2 3)) (caddr '(1 2 3)) (list-ref '(1 2 3) 1) (last '(1 2 3)))"),
+               Ok(list(&[2, 3, 2, 3])));
+    assert_eq!(s("(list-tail '(1 2 3) 2)"), Ok(list(&[3])));
+    assert_eq!(s("(list (list? '(1 2)) (list? '(1 . 2)) (list? '()))"), s("'(#t #f #t)"));
+    assert_eq!(s("(filter (lambda (x) (not (= x 1))) '(1 2 3))"), Ok(list(&[2, 3])));
+    assert_eq!(s("(remove (lambda (x) (not (= x 1))) '(1 2 3))"), Ok(list(&[1])));
+    assert_eq!(s("(fold-left - 10 '(1 2))"), Ok(Value::Int(7)));
+    assert_eq!(s("(fold-right cons '() '(1 2))"), Ok(list(&[1, 2])));
+    assert_eq!(s("(list (reduce + 0 '(1 2 3)) (reduce + 0 '()))"), Ok(list(&[6, 0])));
+    assert_eq!(s("(list (any zero? '(1 0)) (any zero? '(1)) (every zero? '(0 0)) (every zero? '(0 1)))"),
+               s("'(#t #f #t #f)"));
+    assert_eq!(s("(list (member 2 '(1 2 3)) (member 4 '(1 2 3)))"), s("'((2 3) #f)"));
+    assert_eq!(s("(assoc \"b\" '((\"a\" . 1) (\"b\" . 2)))"), s("'(\"b\" . 2)"));
+    assert_eq!(s("(list (assoc 2 '((1 . a) (2 . b)) =) (assoc 2.0 '((1 . a) (2 . b)) =) (assoc 2.0 '((2 . b))))"),
+               s("'((2 . b) (2 . b) #f)"));
+    assert_eq!(s("(range 0 3)"), Ok(list(&[0, 1, 2])));
+    assert_eq!(s("(length (filter (lambda (x) (not (zero? x))) (range 0 5000)))"), Ok(Value::Int(4999)));
+    assert_eq!(s("(length (downto 5000 add1))"), Ok(Value::Int(5000)));
+    assert_eq!(s("(define total 0) (for-each (lambda (x) (set! total (+ total x))) '(1 2 3)) total"),
+               Ok(Value::Int(6)));
+}
+
+#[test]
+fn downto() {
+    assert_eq!(s("(define (fib a) (if (or (eq? a 1) (eq? a 2)) 1 (+ (fib (sub1 a)) (fib (- a 2)))))
+                  (downto 6 fib)"),
+               Ok(list(&[8, 5, 3, 2, 1, 1])));
+}
+
+#[test]
+fn can_be_redefined_and_disabled() {
+    assert_eq!(s("(define (sub1 x) 'mine) (sub1 1)"), s("'mine"));
+    assert_eq!(Interpreter::bare(Profile::Full).eval_str("(sub1 1)"), Err(Error::Unbound(Symbol::intern("sub1"))));
+    assert_eq!(Interpreter::bare(Profile::Full).eval_str("(- 1 1)"), Ok(Value::Int(0)));
+}
+
+#[test]
+fn modules_see_the_prelude() {
+    assert_eq!(s("(module m (export f) (define (f xs) (reverse xs))) (import m) (f '(1 2))"), Ok(list(&[2, 1])));
+}
+
+#[test]
+fn promises() {
+    assert_eq!(s("(define count 0) (define p (delay (begin (set! count (+ count 1)) count)))
+                  (list (force p) (force p) count)"), Ok(list(&[1, 1, 1])));
+    assert_eq!(s("(list (force (make-promise 5)) (force 6) (promise? (delay 1)) (promise? 1))"), s("'(5 6 #t #f)"));
+    assert_eq!(s("(define p (delay 1)) (eq? p (make-promise p))"), Ok(Value::Bool(true)));
+    assert_eq!(s("(define (loop n) (if (zero? n) (delay 'done) (delay-force (loop (- n 1))))) (force (loop 10000))"),
+               s("'done"));
+    assert_eq!(s("(force (delay-force 1))"), Err(Error::Type(String::from("expected promise from delay-force, got 1"))));
+    assert_eq!(s("(define x 1) (define p (delay x)) (set! x 2) (force p)"), Ok(Value::Int(2)));
+}
+
+#[test]
+fn streams() {
+    assert_eq!(s("(stream->list (stream-take 5 (stream-from 0)))"), Ok(list(&[0, 1, 2, 3, 4])));
+    assert_eq!(s("(define (fibs a b) (stream-cons a (fibs b (+ a b))))
+                  (stream->list (fibs 1 1) 8)"), Ok(list(&[1, 1, 2, 3, 5, 8, 13, 21])));
+    assert_eq!(s("(stream->list (stream-map (lambda (x) (* x x)) (stream-filter (lambda (x) (not (= x 2))) (stream-from 1))) 3)"),
+               Ok(list(&[1, 9, 16])));
+    assert_eq!(s("(stream-ref (stream-from 10) 5)"), Ok(Value::Int(15)));
+    assert_eq!(s("(stream->list (list->stream '(1 2 3)))"), Ok(list(&[1, 2, 3])));
+    assert_eq!(s("(list (stream-pair? (stream-from 0)) (stream-pair? '(1 2)) (stream-null? stream-null))"),
+               s("'(#t #f #t)"));
+    assert_eq!(s("(define forced 0) (define s (stream-cons 1 (begin (set! forced 1) stream-null))) forced"),
+               Ok(Value::Int(0)));
+    assert_eq!(s("(define (cons a b) 'x) (stream-car (stream-cdr (stream-cons 1 (stream-cons 2 stream-null))))"),
+               Ok(Value::Int(2)));
+}
+
+#[test]
+fn generators() {
+    assert_eq!(s("(generator->list (generator 1 2 3))"), Ok(list(&[1, 2, 3])));
+    assert_eq!(s("(define (count-from n) (make-generator (lambda (yield) (define (go i) (yield i) (go (+ i 1))) (go n))))
+                  (generator->list (gmap (lambda (x) (* x x)) (gfilter (lambda (x) (not (= x 2))) (count-from 0))) 3)"),
+               Ok(list(&[0, 1, 9])));
+    assert_eq!(s("(generator->list (gtake (list->generator '(1 2 3 4)) 2))"), Ok(list(&[1, 2])));
+    assert_eq!(s("(generator-fold + 0 (make-generator (lambda (yield) (for-each yield '(1 2 3)))))"),
+               Ok(Value::Int(6)));
+    assert_eq!(s("(define seen '()) (generator-for-each (lambda (x) (set! seen (cons x seen))) (generator 1 2)) seen"),
+               Ok(list(&[2, 1])));
+    assert_eq!(s("(define p (open-input-string \"a\\nb\\n\")) (generator->list (lambda () (read-line p)))"),
+               s("'(\"a\" \"b\")"));
+}